@@ -0,0 +1,157 @@
+use std::fs;
+use std::io::{self, Write};
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::calibrate::{self, Calibration};
+use sa2_piece_gen::constraint::{PieceConstraint, SetConstraints};
+use sa2_piece_gen::stage_spec::StageSpec;
+use sa2_piece_gen::table::{Cell, Format, TableWriter};
+
+use crate::{CliError, CliResult};
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    crate::stage_options(&mut opts);
+    opts.optopt("i", "input", "read observed sets from this file, one per line", "FILE");
+    opts.optopt("b", "begin", "smallest pre-calls value to try (default 0)", "RNG_CALLS");
+    opts.optopt("e", "end", "pre-calls value to stop at (default 100000)", "RNG_CALLS");
+    opts.optopt("", "max-offset", "RNG calls a load may come in after the pre-calls (default 0)", "RNG_CALLS");
+    opts.optflag("w", "write", "write the result to the stage spec file given with -s or --specs");
+    opts.optflag("", "force", "write with -w even if the result could be a coincidence");
+    crate::format_option(&mut opts, Some("text"));
+    crate::jobs_option(&mut opts);
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} -p PLATFORM -s STAGE [OPTIONS] [P1 P2 P3]...", program);
+    println!("{}", opts.usage(&brief));
+    println!("Finds the stage's pre-calls from sets seen right after loading it. Each set is");
+    println!("three piece descriptors, given on the command line or one set per line with -i");
+    println!("(# starts a comment). Descriptors are written like for 'sa2pg search', and X");
+    println!("marks a slot that was not seen.");
+    println!();
+    println!("A pre-calls value explains a set if it is generated at most --max-offset calls");
+    println!("after it. The report gives the value explaining the most sets, the offset of");
+    println!("each set, the best value elsewhere, and how many values in the range would");
+    println!("explain as many sets by chance. With -w the value is written to the spec file,");
+    println!("as the offset of the entry path if --entry is given. -w needs -s to name a spec");
+    println!("file or --specs a directory, so the bundled specs are never overwritten, and");
+    println!("refuses when more than {} values would explain as many by chance, unless", calibrate::MAX_FALSE_MATCHES);
+    println!("--force is given.");
+    println!();
+    println!("The other formats have one row per observed set with the fields set (the");
+    println!("descriptors), offset (N/A if unexplained), pre_calls, explained, runner_up and");
+    println!("runner_up_explained (N/A if none) and false_matches (in e notation), where all");
+    println!("but the first two are the same on every row.");
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let stage = crate::stage_args_to_update(matches)?;
+    if matches.opt_present("w") {
+        stage.check_writable()?;
+    }
+    let mut lines: Vec<String> = Vec::new();
+    if let Some(path) = matches.opt_str("i") {
+        let text = fs::read_to_string(&path).map_err(CliError::context(&format!("reading {}", path)))?;
+        lines.extend(text.lines()
+            .map(|line| line.split('#').next().unwrap_or("").trim().to_string())
+            .filter(|line| !line.is_empty()));
+    }
+    lines.extend(matches.free.chunks(3).map(|set| set.join(" ")));
+
+    let observations = lines.iter()
+        .map(|line| parse_observation(line, &stage.spec))
+        .collect::<Result<Vec<_>, _>>()?;
+    if observations.is_empty() {
+        return Err(CliError::usage("expected at least one observed set"));
+    }
+
+    let begin = crate::optional(matches, "b")?.unwrap_or(0);
+    let end = crate::optional(matches, "e")?.unwrap_or(100_000);
+    if end <= begin {
+        return Err(CliError::usage("the range to search is empty"));
+    }
+    let max_offset = crate::optional(matches, "max-offset")?.unwrap_or(0);
+    let jobs = crate::jobs(matches)?;
+
+    let calibration = with_platform!(stage.platform, stage.sort, calibrate::calibrate(&stage.spec, &observations, begin..end, max_offset, jobs))
+        .ok_or_else(|| CliError::usage("the range to search is empty"))?;
+    match crate::table_format(matches, "text")? {
+        None => print_report(&calibration, &lines, stage.spec.pre_calls)?,
+        Some(format) => write_table(&calibration, &lines, format)?,
+    }
+
+    if matches.opt_present("w") {
+        if calibration.explained == 0 {
+            return Err(CliError::failure("no pre-calls value explains any observed set"));
+        }
+        if !calibration.is_confident() && !matches.opt_present("force") {
+            return Err(CliError::failure(format!("not writing: {:.3e} values would explain as many sets by chance; add observations or pass --force", calibration.false_matches())));
+        }
+        stage.save_pre_calls(calibration.pre_calls)
+            .map_err(CliError::context(&format!("writing {}", stage.spec_path.display())))?;
+        eprintln!("Wrote pre-calls {}{} to {}", calibration.pre_calls, stage.entry_note(), stage.spec_path.display());
+    }
+    Ok(())
+}
+
+/// Reads one observed set. Grabbed slots make no sense on a fresh load.
+fn parse_observation(line: &str, spec: &StageSpec) -> Result<SetConstraints, CliError> {
+    let descriptors: Vec<&str> = line.split_whitespace().collect();
+    if descriptors.len() != 3 {
+        return Err(CliError::usage(format!("'{}': expected 3 piece descriptors", line)));
+    }
+    let slot = |idx: usize| {
+        let constraint = descriptors[idx].parse::<PieceConstraint>()
+            .map_err(|e| CliError::usage(format!("'{}': piece {}: {}", line, idx + 1, e)))?;
+        match constraint {
+            PieceConstraint::GrabbedId(_) => Err(CliError::usage(format!("'{}': piece {}: grabbed pieces are not allowed", line, idx + 1))),
+            _ => Ok(constraint),
+        }
+    };
+
+    let constraints = SetConstraints::new(slot(0)?, slot(1)?, slot(2)?);
+    constraints.validate(spec).map_err(|e| CliError::usage(format!("'{}': {}", line, e)))?;
+    Ok(constraints)
+}
+
+fn print_report(calibration: &Calibration, lines: &[String], current: u32) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "pre-calls {} explains {} of {} sets (spec has {})", calibration.pre_calls, calibration.explained, lines.len(), current)?;
+    for (line, offset) in lines.iter().zip(calibration.offsets.iter()) {
+        match offset {
+            Some(offset) => writeln!(out, "  offset {:<6} {}", offset, line)?,
+            None => writeln!(out, "  unexplained   {}", line)?,
+        }
+    }
+    match calibration.runner_up {
+        Some((pre_calls, explained)) => writeln!(out, "runner-up: pre-calls {} explains {}", pre_calls, explained)?,
+        None => writeln!(out, "runner-up: none")?,
+    }
+    writeln!(out, "values explaining as many by chance: {:.3e}", calibration.false_matches())
+}
+
+fn write_table(calibration: &Calibration, lines: &[String], format: Format) -> io::Result<()> {
+    let headers = ["set", "offset", "pre_calls", "explained", "runner_up", "runner_up_explained", "false_matches"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let na = || Cell::Text("N/A".to_string());
+    let stdout = io::stdout();
+    let mut table = TableWriter::new(stdout.lock(), format, headers)?;
+    for (line, offset) in lines.iter().zip(calibration.offsets.iter()) {
+        table.write_row(&[
+            Cell::Text(line.clone()),
+            offset.map_or_else(na, |offset| Cell::Int(offset as i64)),
+            Cell::Int(calibration.pre_calls as i64),
+            Cell::Int(calibration.explained as i64),
+            calibration.runner_up.map_or_else(na, |(pre_calls, _)| Cell::Int(pre_calls as i64)),
+            calibration.runner_up.map_or_else(na, |(_, explained)| Cell::Int(explained as i64)),
+            Cell::Text(format!("{:.3e}", calibration.false_matches())),
+        ])?;
+    }
+    table.finish()
+}
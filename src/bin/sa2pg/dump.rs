@@ -0,0 +1,126 @@
+use std::fs::File;
+use std::io::{self, Write};
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::game_files::{GameFiles, Stage};
+use sa2_piece_gen::load_model;
+use sa2_piece_gen::stage_spec::StageSpec;
+use sa2_piece_gen::table::{Cell, Format, TableWriter};
+
+use crate::{CliError, CliResult};
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    opts.optopt("", "set", "read a SET file (raw or PRS-compressed)", "FILE");
+    opts.optopt("g", "game", "read the SET file of -s STAGE from this game directory or disc image", "DIR");
+    opts.optopt("s", "stage", "stage name for -g, or the level of a --set file for -m", "STAGE");
+    opts.optopt("", "ram", "read a Dolphin memory dump of a GC stage in progress", "FILE");
+    opts.optopt("m", "model", "take pre-calls for --set or -g from this load model (see 'sa2pg precalls')", "FILE");
+    opts.optflag("", "process", "read the running PC game (Windows only)");
+    crate::format_option(&mut opts, Some("spec"));
+    opts.optopt("o", "output", "write to this file instead of standard output", "FILE");
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} (--set FILE | -g DIR -s STAGE | --ram FILE | --process) [-o FILE]", program);
+    println!("{}", opts.usage(&brief));
+    println!("Writes the stage spec as JSON. Specs read from SET files use the default");
+    println!("pre-calls, or the ones a load model gives with -m; memory dumps and the");
+    println!("running game give the current RNG index.");
+    println!();
+    println!("The spec format is the stage-spec JSON the other commands read. The other");
+    println!("formats list one piece per row with the fields list (slot1, slot2, slot3 or");
+    println!("enemy), piece, x, y and z.");
+    crate::print_field_notes();
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let sources = ["set", "g", "ram", "process"].iter()
+        .filter(|name| matches.opt_present(name))
+        .count();
+    if sources != 1 {
+        return Err(CliError::usage("expected exactly one of --set, -g, --ram or --process"));
+    }
+
+    let mut spec = if let Some(path) = matches.opt_str("set") {
+        let file = File::open(&path).map_err(CliError::context(&format!("opening {}", path)))?;
+        StageSpec::from_set_read(file).map_err(CliError::context(&format!("reading {}", path)))?
+    }
+    else if let Some(dir) = matches.opt_str("g") {
+        let stage_name = crate::required::<String>(matches, "s")?;
+        let stage = Stage::from_name(&stage_name)
+            .ok_or_else(|| CliError::usage(format!("unknown stage '{}'", stage_name)))?;
+        let game = GameFiles::open(&dir).map_err(CliError::context(&format!("opening {}", dir)))?;
+        StageSpec::from_game_files(&game, stage).map_err(CliError::context("reading SET file"))?
+    }
+    else if let Some(path) = matches.opt_str("ram") {
+        // The memory dump loader reads fixed GC addresses and panics on bad input.
+        File::open(&path).map_err(CliError::context(&format!("opening {}", path)))?;
+        StageSpec::from_path::<sa2_piece_gen::Gc, _>(path)
+    }
+    else {
+        from_process()?
+    };
+
+    if let Some(model_path) = matches.opt_str("m") {
+        if matches.opt_present("ram") || matches.opt_present("process") {
+            return Err(CliError::usage("-m needs --set or -g"));
+        }
+        let stage = matches.opt_str("s").map(|name| Stage::from_name(&name).ok_or_else(|| CliError::usage(format!("unknown stage '{}'", name)))).transpose()?;
+        let model = crate::precalls::load_model(&model_path)?;
+        let sets = crate::precalls::read_sets(matches, stage)?;
+        spec.pre_calls = model.count_calls(stage.map(|stage| stage.level_id()), load_model::object_ids(&sets)).pre_calls;
+    }
+
+    let output: Box<dyn Write> = match matches.opt_str("o") {
+        Some(path) => Box::new(File::create(&path).map_err(CliError::context(&format!("creating {}", path)))?),
+        None => Box::new(io::stdout()),
+    };
+    let mut output = io::BufWriter::new(output);
+    match crate::table_format(matches, "spec")? {
+        None => {
+            serde_json::to_writer_pretty(&mut output, &spec).map_err(CliError::context("writing stage spec"))?;
+            writeln!(output)?;
+            output.flush()?;
+        }
+        Some(format) => write_pieces(&spec, format, output)?,
+    }
+    Ok(())
+}
+
+fn write_pieces<W>(spec: &StageSpec, format: Format, output: W) -> io::Result<()>
+    where W: Write,
+{
+    let headers = ["list", "piece", "x", "y", "z"].iter().map(|s| s.to_string()).collect();
+    let mut table = TableWriter::new(output, format, headers)?;
+    let lists = [
+        ("slot1", &spec.slot1_pieces),
+        ("slot2", &spec.slot2_pieces),
+        ("slot3", &spec.slot3_pieces),
+        ("enemy", &spec.enemy_pieces),
+    ];
+    for (list, pieces) in lists.iter() {
+        for piece in pieces.iter() {
+            table.write_row(&[
+                Cell::Text(list.to_string()),
+                Cell::Text(format!("{:04X}", piece.id)),
+                Cell::Float(piece.position.x),
+                Cell::Float(piece.position.y),
+                Cell::Float(piece.position.z),
+            ])?;
+        }
+    }
+    table.finish()
+}
+
+#[cfg(windows)]
+fn from_process() -> Result<StageSpec, CliError> {
+    Ok(StageSpec::from_process::<sa2_piece_gen::Pc>("sonic2app.exe"))
+}
+
+#[cfg(not(windows))]
+fn from_process() -> Result<StageSpec, CliError> {
+    Err(CliError::failure("--process is only supported on Windows"))
+}
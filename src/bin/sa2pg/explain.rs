@@ -0,0 +1,190 @@
+use std::io::{self, Write};
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::constraint::GRABBED_ID;
+use sa2_piece_gen::explain::{self, Explanation, SlotPick};
+use sa2_piece_gen::hint_lookup::HintLookup;
+use sa2_piece_gen::rng::Rng;
+use sa2_piece_gen::stage_spec::{Emerald, StageSpec};
+use sa2_piece_gen::table::{Cell, Format, TableWriter};
+use sa2_piece_gen::Platform;
+
+use crate::{CliError, CliResult};
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    crate::stage_options(&mut opts);
+    opts.optopt("i", "index", "RNG index to explain", "RNG_CALLS");
+    opts.optopt("", "state", "RNG state to explain", "HEX_STATE");
+    opts.optopt("", "offset", "RNG calls after the stage's pre-calls to explain", "RNG_CALLS");
+    crate::hint_options(&mut opts);
+    crate::format_option(&mut opts, Some("text"));
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} -p PLATFORM -s STAGE (-i INDEX | --state STATE | --offset N) [OPTIONS] [P1 P2 P3]", program);
+    println!("{}", opts.usage(&brief));
+    println!("Shows each step of generating the set: the raw RNG values and the indices");
+    println!("they scale to, the sorted p2 candidates with their distances to p1, the sorted");
+    println!("p3 candidates with their cross-product magnitudes, and which enemy piece was");
+    println!("taken out of the enemy list when p1 is an enemy.");
+    println!();
+    println!("P1 P2 P3 mark grabbed slots the same way as 'sa2pg search': G0A03 for a piece");
+    println!("grabbed in the previous life, X otherwise.");
+    println!();
+    println!("The other formats have one row per candidate with the fields slot (1-3),");
+    println!("state (RNG state before the draw), rand_val, index (the candidate it scales");
+    println!("to), rank (position in the candidate order), piece, key (distance to p1 for");
+    println!("p2, cross-product magnitude for p3, N/A for p1), picked (1 or 0) and hint when");
+    println!("hints are loaded. A grabbed slot has a single row with N/A for the draw. Only");
+    println!("the text format says which enemy was taken out of the enemy list.");
+    crate::print_field_notes();
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let stage = crate::stage_args(matches)?;
+    let lookup = crate::hint_lookup(matches, stage.stage)?;
+    let constraints = crate::table::grabbed_constraints(&matches.free, &stage.spec)?;
+    let grabbed = constraints.grabbed_pieces(&stage.spec);
+
+    let index = crate::optional::<u32>(matches, "i")?;
+    let state = matches.opt_str("state").map(|s| crate::parse_state(&s)).transpose()?;
+    let offset = crate::optional::<u32>(matches, "offset")?;
+    let start = match (index, state, offset) {
+        (Some(index), None, None) => Start::Index(index),
+        (None, Some(state), None) => Start::State(state),
+        (None, None, Some(offset)) => Start::Index(stage.spec.pre_calls.wrapping_add(offset)),
+        _ => return Err(CliError::usage("expected exactly one of -i, --state or --offset")),
+    };
+
+    let explanation = with_platform!(stage.platform, stage.sort, explain_at(&stage.spec, start, grabbed));
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match crate::table_format(matches, "text")? {
+        None => write_text(&mut out, &explanation, lookup.as_ref())?,
+        Some(format) => write_table(out, &explanation, lookup.as_ref(), format)
+            .map_err(CliError::context("writing explanation"))?,
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum Start {
+    Index(u32),
+    State(u32),
+}
+
+fn explain_at<P>(spec: &StageSpec, start: Start, grabbed: [Option<Emerald>; 3]) -> Explanation
+    where P: Platform,
+{
+    let r = match start {
+        Start::Index(index) => Rng::at_index::<P::Consts>(index),
+        Start::State(state) => Rng::new(state),
+    };
+    explain::explain::<P>(spec, r, grabbed)
+}
+
+fn write_text<W>(out: &mut W, explanation: &Explanation, lookup: Option<&HintLookup>) -> io::Result<()>
+    where W: Write,
+{
+    writeln!(out, "Start state {:08X}", explanation.start_state)?;
+
+    write_pick(out, "p1", &explanation.p1, "", lookup)?;
+    if let Some(removed) = explanation.removed_enemy {
+        write!(out, "  removed enemy #{} ({:04X}) from the enemy list", removed.enemy_index, removed.piece.id)?;
+        match removed.moved {
+            Some(moved) => writeln!(out, "; {:04X} moved into its place", moved.id)?,
+            None => writeln!(out, "; it was the last one")?,
+        }
+    }
+    write_pick(out, "p2", &explanation.p2, "distance to p1", lookup)?;
+    write_pick(out, "p3", &explanation.p3, "cross-product magnitude", lookup)?;
+
+    writeln!(out)?;
+    writeln!(out, "End state {:08X} after {} RNG calls", explanation.end_state, explanation.calls)
+}
+
+fn write_table<W>(out: W, explanation: &Explanation, lookup: Option<&HintLookup>, format: Format) -> io::Result<()>
+    where W: Write,
+{
+    let mut headers: Vec<String> = ["slot", "state", "rand_val", "index", "rank", "piece", "key", "picked"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    if lookup.is_some() {
+        headers.push("hint".to_string());
+    }
+
+    let na = || Cell::Text("N/A".to_string());
+    let piece_cells = |piece: &Emerald| {
+        let mut cells = vec![Cell::Text(format!("{:04X}", piece.id))];
+        if let Some(hints) = lookup {
+            cells.push(Cell::Text(if piece.id == GRABBED_ID { "N/A".to_string() } else { hints.lookup_piece(piece.id).h1.replace('\n', " ") }));
+        }
+        cells
+    };
+
+    let mut table = TableWriter::new(out, format, headers)?;
+    for (slot, pick) in [&explanation.p1, &explanation.p2, &explanation.p3].iter().enumerate() {
+        let draw = match pick.draw {
+            Some(draw) => draw,
+            None => {
+                let mut row = vec![Cell::Int(slot as i64 + 1), na(), na(), na(), na()];
+                let mut cells = piece_cells(&pick.piece);
+                row.push(cells.remove(0));
+                row.extend([na(), Cell::Int(1)]);
+                row.extend(cells);
+                table.write_row(&row)?;
+                continue;
+            }
+        };
+
+        for (rank, candidate) in pick.candidates.iter().enumerate() {
+            let mut row = vec![
+                Cell::Int(slot as i64 + 1),
+                Cell::Text(format!("{:08X}", draw.state)),
+                Cell::Int(draw.rand_val as i64),
+                Cell::Int(draw.index as i64),
+                Cell::Int(rank as i64),
+            ];
+            let mut cells = piece_cells(&candidate.piece);
+            row.push(cells.remove(0));
+            row.push(candidate.key.map_or_else(na, Cell::Float));
+            row.push(Cell::Int((rank == draw.index) as i64));
+            row.extend(cells);
+            table.write_row(&row)?;
+        }
+    }
+    table.finish()
+}
+
+fn write_pick<W>(out: &mut W, name: &str, pick: &SlotPick, key_name: &str, lookup: Option<&HintLookup>) -> io::Result<()>
+    where W: Write,
+{
+    writeln!(out)?;
+    let draw = match pick.draw {
+        Some(draw) => draw,
+        None => return writeln!(out, "{}: grabbed in a previous life, no RNG call", name),
+    };
+
+    writeln!(out, "{}: state {:08X} -> gen_val {} (0x{:04X}) -> index {} of {}",
+        name, draw.state, draw.rand_val, draw.rand_val, draw.index, pick.candidates.len())?;
+    if !key_name.is_empty() {
+        writeln!(out, "  candidates by {}:", key_name)?;
+    }
+    for (idx, candidate) in pick.candidates.iter().enumerate() {
+        let marker = if idx == draw.index { "=>" } else { "  " };
+        write!(out, "  {} {:3} {:04X}", marker, idx, candidate.piece.id)?;
+        if let Some(key) = candidate.key {
+            write!(out, " {:>14.4}", key)?;
+        }
+        if let Some(hints) = lookup {
+            write!(out, "  {}", hints.lookup_piece(candidate.piece.id).h1.replace('\n', " "))?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,431 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::ops::Range;
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::constraint::{PieceConstraint, PieceMatch, SetConstraints};
+use sa2_piece_gen::game_files::{self, HintLanguage};
+use sa2_piece_gen::hint_lookup::HintLookup;
+use sa2_piece_gen::posterior::{Evidence, Posterior};
+use sa2_piece_gen::stage_spec::Emerald;
+
+use crate::{CliError, CliResult, PlatformArg, SortArg, StageArgs};
+
+const HELP: &[(&str, &str)] = &[
+    ("stage NAME [ENTRY]", "pick a stage, optionally with an entry path"),
+    ("hint SLOT TEXT", "a slot's hint contains TEXT"),
+    ("seen SLOT PIECES", "a slot's piece matches a descriptor (0A03, 0105,0107, @enemy, !0A03)"),
+    ("got PIECE", "the piece was found and collected"),
+    ("empty PIECE...", "the pieces' locations were checked and empty"),
+    ("miss RATE", "chance of overlooking a piece at a checked location"),
+    ("find TEXT", "list the pieces whose hints loosely match TEXT"),
+    ("sets [N]", "list the likeliest remaining sets"),
+    ("window BEGIN END", "start over with the set anywhere in this range of RNG indices"),
+    ("die GAP[~TOL]", "start the next life GAP RNG calls later, grabbing what was got"),
+    ("undo", "take back the last hint, seen, got or empty"),
+    ("help", "list the commands"),
+    ("quit", "leave"),
+];
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    crate::stage_options(&mut opts);
+    opts.optopt("b", "begin", "first RNG index the set may be at (default 0)", "RNG_CALLS");
+    opts.optopt("e", "end", "RNG index to stop at (default 1000000)", "RNG_CALLS");
+    opts.optopt("t", "tolerance", "tolerance of 'die' gaps given without one (default 500)", "RNG_CALLS");
+    opts.optopt("", "miss", "chance of overlooking a piece at a checked location (default 0)", "RATE");
+    opts.optopt("n", "count", "number of locations to show (default 10)", "N");
+    crate::hint_options(&mut opts);
+    crate::jobs_option(&mut opts);
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} -p PLATFORM [-s STAGE] [OPTIONS]", program);
+    println!("{}", opts.usage(&brief));
+    println!("Keeps track of what is known during a hunt and shows the likeliest piece");
+    println!("locations after every command, like 'sa2pg locate'. Slots are 1 to 3 and");
+    println!("pieces hexadecimal IDs. Hints need -l or -g; -g also finds the hints of each");
+    println!("stage picked. 'die' carries what is known over to the next life: each RNG");
+    println!("index still possible moves on by the gap, spread over its tolerance, like the");
+    println!("gaps of 'sa2pg identify'. Commands:");
+    println!();
+    print_commands(&mut io::stdout().lock()).unwrap_or(());
+    println!();
+    println!("    sa2pg hunt -p gc -s ph -g GAME");
+    println!("    hunt> hint 3 rock pillar");
+    println!("    hunt> empty 0705");
+}
+
+fn print_commands<W: Write>(out: &mut W) -> io::Result<()> {
+    for (command, about) in HELP {
+        writeln!(out, "    {:<20}{}", command, about)?;
+    }
+    Ok(())
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let platform = crate::required::<PlatformArg>(matches, "p")?;
+    let begin = crate::optional(matches, "b")?.unwrap_or(0);
+    let end = crate::optional(matches, "e")?.unwrap_or(1_000_000);
+    if end <= begin {
+        return Err(CliError::usage("the range to search is empty"));
+    }
+    let miss_rate = crate::optional(matches, "miss")?.unwrap_or(0.0);
+    if !(0.0..=1.0).contains(&miss_rate) {
+        return Err(CliError::usage("--miss has to be between 0 and 1"));
+    }
+
+    let mut hunt = Hunt {
+        platform,
+        sort: crate::sort(matches)?,
+        specs: matches.opt_str("specs"),
+        hint_source: matches.opt_str("l").or_else(|| matches.opt_str("g")),
+        lang: crate::hint_language(matches)?,
+        window: begin..end,
+        tolerance: crate::optional(matches, "t")?.unwrap_or(500),
+        miss_rate,
+        shown: crate::optional(matches, "n")?.unwrap_or(10),
+        jobs: crate::jobs(matches)?,
+        stage: None,
+    };
+    if let Some(stage_arg) = matches.opt_str("s") {
+        hunt.pick_stage(&stage_arg, matches.opt_str("entry")).map_err(CliError::usage)?;
+    }
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let clear = out.is_terminal();
+    let mut message = "Enter 'help' for the commands.".to_string();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if clear {
+            write!(out, "\x1b[2J\x1b[H")?;
+        }
+        hunt.render(&mut out)?;
+        if !message.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "{}", message)?;
+        }
+        write!(out, "hunt> ")?;
+        out.flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        let line = line.trim();
+        if line == "quit" || line == "exit" {
+            break;
+        }
+        message = match hunt.execute(line) {
+            Ok(message) => message,
+            Err(e) => format!("error: {}", e),
+        };
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+struct Hunt {
+    platform: PlatformArg,
+    sort: SortArg,
+    specs: Option<String>,
+    hint_source: Option<String>,
+    lang: HintLanguage,
+    window: Range<u32>,
+    /// Tolerance of gaps given without one.
+    tolerance: u32,
+    miss_rate: f64,
+    shown: usize,
+    jobs: usize,
+    stage: Option<Life>,
+}
+
+/// What is known of the current life in the picked stage.
+struct Life {
+    args: StageArgs,
+    lookup: Option<HintLookup>,
+    number: usize,
+    /// Pieces grabbed in earlier lives, by slot.
+    grabbed: [Option<u16>; 3],
+    /// Pieces collected this life.
+    collected: Vec<u16>,
+    posterior: Posterior,
+    /// A description of each piece of evidence in the posterior.
+    notes: Vec<String>,
+}
+
+impl Hunt {
+    fn pick_stage(&mut self, stage_arg: &str, entry: Option<String>) -> Result<(), String> {
+        let args = crate::load_spec(self.platform, self.sort, stage_arg, self.specs.as_deref(), entry, false)
+            .map_err(|e| e.to_string())?;
+        let lookup = match self.hint_source {
+            Some(ref source) => Some(game_files::load_hints(source, args.stage, self.lang)
+                .map_err(|e| format!("loading hints: {}", e))?),
+            None => None,
+        };
+        let posterior = self.posterior(&args, [None; 3]);
+        self.stage = Some(Life {
+            args,
+            lookup,
+            number: 1,
+            grabbed: [None; 3],
+            collected: Vec::new(),
+            posterior,
+            notes: Vec::new(),
+        });
+        Ok(())
+    }
+
+    fn posterior(&self, args: &StageArgs, grabbed: [Option<u16>; 3]) -> Posterior {
+        let grabbed = grabbed_pieces(args, grabbed);
+        with_platform!(self.platform, self.sort, Posterior::new(&args.spec, grabbed, self.window.clone(), self.jobs))
+    }
+
+    /// Runs one command, returning a message to show.
+    fn execute(&mut self, line: &str) -> Result<String, String> {
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        match command {
+            "" => Ok(String::new()),
+            "help" => {
+                let mut text = Vec::new();
+                print_commands(&mut text).map_err(|e| e.to_string())?;
+                Ok(String::from_utf8_lossy(&text).trim_end().to_string())
+            }
+            "stage" => {
+                let mut args = rest.split_whitespace();
+                let name = args.next().ok_or("expected a stage")?;
+                self.pick_stage(name, args.next().map(str::to_string))?;
+                Ok(String::new())
+            }
+            "window" => {
+                let bounds: Vec<u32> = rest.split_whitespace()
+                    .map(|arg| arg.parse().map_err(|e| format!("bad RNG index '{}': {}", arg, e)))
+                    .collect::<Result<_, _>>()?;
+                match bounds[..] {
+                    [begin, end] if begin < end => self.window = begin..end,
+                    _ => return Err("expected BEGIN END with BEGIN before END".to_string()),
+                }
+                if let Some(life) = self.stage.take() {
+                    let mut posterior = self.posterior(&life.args, life.grabbed);
+                    for evidence in life.posterior.evidence() {
+                        posterior.observe(evidence.clone());
+                    }
+                    self.stage = Some(Life { posterior, ..life });
+                }
+                Ok(String::new())
+            }
+            "die" => {
+                if rest.is_empty() {
+                    return Err("expected the gap in RNG calls to the next life, e.g. 'die 2400~300'".to_string());
+                }
+                let gap = crate::identify::parse_gap(rest, self.tolerance)?;
+                let mut life = self.stage.take().ok_or("pick a stage first")?;
+                let grabbed = match life.posterior.remaining() {
+                    0 => Err("no set fits what is known; undo something first".to_string()),
+                    _ => life.grab_collected(),
+                };
+                if grabbed.is_ok() {
+                    let pieces = grabbed_pieces(&life.args, life.grabbed);
+                    life.posterior = with_platform!(self.platform, self.sort, Posterior::next_life(&life.posterior, &life.args.spec, pieces, gap, self.jobs));
+                    self.window = life.posterior.span();
+                    life.number += 1;
+                    life.collected.clear();
+                    life.notes.clear();
+                }
+                self.stage = Some(life);
+                grabbed.map(|()| String::new())
+            }
+            "miss" => {
+                let rate: f64 = rest.parse().map_err(|e| format!("bad rate '{}': {}", rest, e))?;
+                if !(0.0..=1.0).contains(&rate) {
+                    return Err("the rate has to be between 0 and 1".to_string());
+                }
+                self.miss_rate = rate;
+                Ok(format!("Pieces are overlooked at checked locations {}% of the time.", rate * 100.0))
+            }
+            _ => {
+                let miss_rate = self.miss_rate;
+                let life = self.stage.as_mut().ok_or("pick a stage first")?;
+                life.execute(command, rest, miss_rate)
+            }
+        }
+    }
+
+    fn render<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let life = match self.stage {
+            Some(ref life) => life,
+            None => return writeln!(out, "No stage picked; enter 'stage NAME'."),
+        };
+
+        let name = match life.args.stage {
+            Some(stage) => stage.name().to_string(),
+            None => life.args.spec_path.display().to_string(),
+        };
+        writeln!(out, "{} ({}{}), life {}, RNG indices {} to {}", name, self.platform.name(), life.args.entry_note(),
+            life.number, self.window.start, self.window.end)?;
+        let grabbed: Vec<String> = life.grabbed.iter()
+            .enumerate()
+            .filter_map(|(slot, id)| id.map(|id| format!("p{} {:04X}", slot + 1, id)))
+            .collect();
+        if !grabbed.is_empty() {
+            writeln!(out, "Grabbed: {}", grabbed.join(", "))?;
+        }
+        if !life.notes.is_empty() {
+            writeln!(out, "Known: {}", life.notes.join("; "))?;
+        }
+        let remaining = life.posterior.remaining();
+        writeln!(out, "{} possible sets left", remaining)?;
+        if remaining == 0 {
+            return writeln!(out, "Nothing fits; undo something.");
+        }
+
+        writeln!(out)?;
+        writeln!(out, "piece  chance  hint")?;
+        let locations = life.posterior.locations();
+        let unchecked = locations.iter().filter(|location| !life.collected.contains(&location.piece.id));
+        for location in unchecked.take(self.shown) {
+            let line = format!("{:04X}  {:>5.1}%  {}", location.piece.id, location.probability * 100.0, life.hint(location.piece.id));
+            writeln!(out, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// The pieces grabbed in earlier lives, as `CandidateCache::with_grabbed`
+/// takes them.
+fn grabbed_pieces(args: &StageArgs, grabbed: [Option<u16>; 3]) -> [Option<Emerald>; 3] {
+    let slot = |id: Option<u16>| id.map_or(PieceConstraint::DontCare, PieceConstraint::GrabbedId);
+    SetConstraints::new(slot(grabbed[0]), slot(grabbed[1]), slot(grabbed[2]))
+        .grabbed_pieces(&args.spec)
+}
+
+impl Life {
+    fn execute(&mut self, command: &str, rest: &str, miss_rate: f64) -> Result<String, String> {
+        match command {
+            "hint" => {
+                let (slot, text) = self.slot_arg(rest)?;
+                let lookup = self.lookup.as_ref().ok_or("hints need -l or -g")?;
+                // Loose matches are only suggested: recording the wrong piece
+                // as certain would quietly rule out the right sets.
+                let seen = lookup.hint_match(&self.args.spec, text).map_err(|e| {
+                    let found = lookup.search_hints(&self.args.spec, text);
+                    let closest: Vec<String> = found.iter().take(5).map(|&(id, _)| format!("{:04X}", id)).collect();
+                    if closest.is_empty() {
+                        e.to_string()
+                    }
+                    else {
+                        format!("{}; closest: {}. Check them with 'find {}' and record one with 'seen {} PIECES'", e, closest.join(", "), text, slot + 1)
+                    }
+                })?;
+                let note = format!("p{} hint \"{}\" ({})", slot + 1, text, seen);
+                self.observe(Evidence::Slot(slot, seen), note);
+                Ok(String::new())
+            }
+            "seen" => {
+                let (slot, text) = self.slot_arg(rest)?;
+                let seen = text.parse::<PieceMatch>().map_err(|e| e.to_string())?;
+                seen.validate(&self.args.spec).map_err(|e| e.to_string())?;
+                self.observe(Evidence::Slot(slot, seen), format!("p{} is {}", slot + 1, text));
+                Ok(String::new())
+            }
+            "got" => {
+                let id = self.piece_arg(rest)?;
+                self.collected.push(id);
+                self.observe(Evidence::Found(id), format!("got {:04X}", id));
+                Ok(String::new())
+            }
+            "empty" => {
+                let ids = rest.split_whitespace()
+                    .map(|arg| self.piece_arg(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if ids.is_empty() {
+                    return Err("expected a piece".to_string());
+                }
+                for id in ids {
+                    self.observe(Evidence::Empty { id, miss_rate }, format!("{:04X} empty", id));
+                }
+                Ok(String::new())
+            }
+            "undo" => {
+                let evidence = self.posterior.undo().ok_or("nothing to undo")?;
+                if let Evidence::Found(id) = evidence {
+                    self.collected.retain(|&collected| collected != id);
+                }
+                Ok(format!("Took back: {}", self.notes.pop().unwrap_or_default()))
+            }
+            "find" => {
+                let lookup = self.lookup.as_ref().ok_or("hints need -l or -g")?;
+                let found = lookup.search_hints(&self.args.spec, rest);
+                if found.is_empty() {
+                    return Err(format!("no hint matches \"{}\"", rest));
+                }
+                let locations = self.posterior.locations();
+                let lines: Vec<String> = found.iter()
+                    .take(10)
+                    .map(|&(id, _)| {
+                        let chance = locations.iter().find(|location| location.piece.id == id).map_or(0.0, |location| location.probability);
+                        format!("{:04X}  {:>5.1}%  {}", id, chance * 100.0, self.hint(id)).trim_end().to_string()
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            "sets" => {
+                let count = if rest.is_empty() { 10 } else { rest.parse().map_err(|e| format!("bad count '{}': {}", rest, e))? };
+                let lines: Vec<String> = self.posterior.sets()
+                    .iter()
+                    .take(count)
+                    .map(|(set, probability)| {
+                        let ids = set.ids().map(|id| format!("{:04X}", id));
+                        format!("{} {} {}  {:>5.1}%  first at {}", ids[0], ids[1], ids[2], probability * 100.0, set.first_index)
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            _ => Err(format!("unknown command '{}'", command)),
+        }
+    }
+
+    fn observe(&mut self, evidence: Evidence, note: String) {
+        self.posterior.observe(evidence);
+        self.notes.push(note);
+    }
+
+    /// Grabs the pieces collected this life for the next one, in the slot
+    /// the likeliest remaining set has them in.
+    fn grab_collected(&mut self) -> Result<(), String> {
+        let sets = self.posterior.sets();
+        let mut grabbed = self.grabbed;
+        for &id in &self.collected {
+            let (set, _) = sets.iter()
+                .find(|(set, _)| set.ids().contains(&id))
+                .ok_or("no set fits what is known; undo something first")?;
+            let slot = set.ids().iter().position(|&set_id| set_id == id).unwrap();
+            grabbed[slot] = Some(id);
+        }
+        self.grabbed = grabbed;
+        Ok(())
+    }
+
+    /// Parses `SLOT VALUE` with a slot that isn't grabbed.
+    fn slot_arg<'a>(&self, rest: &'a str) -> Result<(usize, &'a str), String> {
+        let (slot, value) = rest.split_once(char::is_whitespace).ok_or("expected SLOT and a value")?;
+        let slot = crate::locate::parse_slot(slot, self.grabbed.map(|id| id.is_some()))?;
+        Ok((slot, value.trim()))
+    }
+
+    fn piece_arg(&self, arg: &str) -> Result<u16, String> {
+        let id = crate::locate::parse_piece(arg, &self.args.spec)?;
+        if self.grabbed.contains(&Some(id)) {
+            return Err(format!("piece {:04X} was grabbed in an earlier life", id));
+        }
+        Ok(id)
+    }
+
+    fn hint(&self, id: u16) -> String {
+        self.lookup.as_ref().map(|lookup| lookup.lookup_piece(id).h1.replace('\n', " ")).unwrap_or_default()
+    }
+}
@@ -0,0 +1,214 @@
+use std::fs;
+use std::io;
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::constraint::PieceMatch;
+use sa2_piece_gen::hint_lookup::HintLookup;
+use sa2_piece_gen::infer::{self, Candidate, Gap, Life};
+use sa2_piece_gen::rng::Rng;
+use sa2_piece_gen::stage_spec::StageSpec;
+use sa2_piece_gen::table::{Cell, Format, TableWriter};
+use sa2_piece_gen::Platform;
+
+use crate::{CliError, CliResult};
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    crate::stage_options(&mut opts);
+    opts.optopt("i", "input", "read lives from this file, one per line", "FILE");
+    opts.optopt("b", "begin", "first RNG index the first life may be at (default 0)", "RNG_CALLS");
+    opts.optopt("e", "end", "RNG index to stop looking for the first life at (default 1000000)", "RNG_CALLS");
+    opts.optopt("t", "tolerance", "tolerance of gaps given without one (default 500)", "RNG_CALLS");
+    opts.optopt("n", "count", "number of candidates to list (default 20)", "N");
+    crate::hint_options(&mut opts);
+    crate::format_option(&mut opts, None);
+    crate::jobs_option(&mut opts);
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} -p PLATFORM -s STAGE [OPTIONS] [LIFE]...", program);
+    println!("{}", opts.usage(&brief));
+    println!("Lists the RNG indices the last life's set may have been generated at, given");
+    println!("what was seen over several lives, likeliest first.");
+    println!();
+    println!("Each LIFE (or line of -i, where # starts a comment) is");
+    println!();
+    println!("    [+GAP[~TOLERANCE]] P1 P2 P3");
+    println!();
+    println!("GAP is the approximate number of RNG calls since the previous life's set was");
+    println!("generated, and is needed for every life but the first. Each slot is X if");
+    println!("nothing is known, a piece descriptor like for 'sa2pg search' (0A03, 0105,0107,");
+    println!("@hidden, !0A03), or hint text in double quotes, which needs -l or -g. A *");
+    println!("after the slot marks the piece as collected that life; it then has to be a");
+    println!("single piece, and later lives don't generate that slot.");
+    println!();
+    println!("    sa2pg identify -p pc -s dc -g GAME '0A03* X \"ROCK\"' '+2400~300 X 0107 X'");
+    println!();
+    println!("Fields: rng_index and state of the last life, probability (share of the");
+    println!("weight of all chains of indices consistent with every life, where a gap");
+    println!("counts less the further it is from the expected one), and path (the likeliest");
+    println!("index of each life, separated by spaces).");
+    crate::print_field_notes();
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let stage = crate::stage_args(matches)?;
+    let lookup = crate::hint_lookup(matches, stage.stage)?;
+    let tolerance = crate::optional(matches, "t")?.unwrap_or(500);
+
+    let mut lines: Vec<String> = Vec::new();
+    if let Some(path) = matches.opt_str("i") {
+        let text = fs::read_to_string(&path).map_err(CliError::context(&format!("reading {}", path)))?;
+        lines.extend(text.lines()
+            .map(|line| line.split('#').next().unwrap_or("").trim().to_string())
+            .filter(|line| !line.is_empty()));
+    }
+    lines.extend(matches.free.iter().cloned());
+    if lines.is_empty() {
+        return Err(CliError::usage("expected at least one life"));
+    }
+
+    let lives = lines.iter()
+        .enumerate()
+        .map(|(idx, line)| parse_life(line, &stage.spec, lookup.as_ref(), tolerance)
+            .map_err(|e| CliError::usage(format!("life {}: {}", idx + 1, e))))
+        .collect::<Result<Vec<_>, _>>()?;
+    infer::validate(&stage.spec, &lives).map_err(|e| CliError::usage(e.to_string()))?;
+
+    let begin = crate::optional(matches, "b")?.unwrap_or(0);
+    let end = crate::optional(matches, "e")?.unwrap_or(1_000_000);
+    if end <= begin {
+        return Err(CliError::usage("the range to search is empty"));
+    }
+    let count = crate::optional(matches, "n")?.unwrap_or(20);
+    let format = crate::format(matches, Format::Csv)?;
+    let jobs = crate::jobs(matches)?;
+
+    with_platform!(stage.platform, stage.sort, write_candidates(&stage.spec, &lives, begin, end, jobs, count, format))
+        .map_err(CliError::context("writing candidates"))
+}
+
+/// Parses `GAP[~TOLERANCE]`, with `tolerance` if none is given.
+pub fn parse_gap(text: &str, tolerance: u32) -> Result<Gap, String> {
+    let (expected, tolerance) = match text.split_once('~') {
+        Some((expected, tolerance)) => (expected, tolerance.parse().map_err(|e| format!("bad gap tolerance '{}': {}", tolerance, e))?),
+        None => (text, tolerance),
+    };
+    let expected = expected.parse().map_err(|e| format!("bad gap '{}': {}", expected, e))?;
+    Ok(Gap { expected, tolerance })
+}
+
+/// Parses `[+GAP[~TOLERANCE]] P1 P2 P3`.
+fn parse_life(line: &str, spec: &StageSpec, lookup: Option<&HintLookup>, tolerance: u32) -> Result<Life, String> {
+    let mut tokens = tokenize(line)?;
+    let gap = match tokens.first() {
+        Some(token) if token.starts_with('+') => {
+            let token = tokens.remove(0);
+            Some(parse_gap(&token[1..], tolerance)?)
+        }
+        _ => None,
+    };
+    if tokens.len() != 3 {
+        return Err("expected 3 slots".to_string());
+    }
+
+    let mut life = Life {
+        seen: [None, None, None],
+        collected: [None; 3],
+        gap,
+    };
+    for (slot, token) in tokens.iter().enumerate() {
+        let (token, collected) = match token.strip_suffix('*') {
+            Some(token) => (token, true),
+            None => (token.as_str(), false),
+        };
+
+        let seen = if token == "X" {
+            None
+        }
+        else if let Some(text) = token.strip_prefix('"') {
+            let lookup = lookup.ok_or_else(|| "hints need -l or -g".to_string())?;
+            Some(lookup.hint_match(spec, text).map_err(|e| format!("p{}: {}", slot + 1, e))?)
+        }
+        else {
+            Some(token.parse::<PieceMatch>().map_err(|e| format!("p{}: {}", slot + 1, e))?)
+        };
+
+        if collected {
+            match seen {
+                Some(PieceMatch::Id(id)) => life.collected[slot] = Some(id),
+                _ => return Err(format!("p{}: a collected piece has to be a single piece", slot + 1)),
+            }
+        }
+        life.seen[slot] = seen;
+    }
+    Ok(life)
+}
+
+/// Splits a line at whitespace, keeping double-quoted text together. Quoted
+/// tokens keep their opening quote to set them apart.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut token = String::new();
+        if c == '"' {
+            chars.next();
+            token.push('"');
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => token.push(c),
+                    None => return Err("unterminated quote".to_string()),
+                }
+            }
+        }
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            token.push(c);
+            chars.next();
+        }
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn write_candidates<P>(spec: &StageSpec, lives: &[Life], begin: u32, end: u32, jobs: usize, count: usize, format: Format) -> io::Result<()>
+    where P: Platform,
+{
+    let candidates = infer::identify::<P>(spec, lives, begin..end, jobs);
+    let headers = ["rng_index", "state", "probability", "path"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let stdout = io::stdout();
+    let mut table = TableWriter::new(stdout.lock(), format, headers)?;
+    for candidate in candidates.iter().take(count) {
+        table.write_row(&row::<P>(candidate))?;
+    }
+    table.finish()
+}
+
+fn row<P>(candidate: &Candidate) -> Vec<Cell>
+    where P: Platform,
+{
+    let path = candidate.path.iter()
+        .map(|index| index.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    vec![
+        Cell::Int(candidate.index as i64),
+        Cell::Text(format!("{:08X}", Rng::at_index::<P::Consts>(candidate.index).get_state())),
+        Cell::Float(candidate.probability as f32),
+        Cell::Text(path),
+    ]
+}
@@ -0,0 +1,92 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::parallel::FULL_PERIOD;
+use sa2_piece_gen::set_index::{self, SetIndex};
+use sa2_piece_gen::stage_spec::StageSpec;
+use sa2_piece_gen::table::{Cell, Format, TableWriter};
+use sa2_piece_gen::Platform;
+
+use crate::{CliError, CliResult};
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    crate::stage_options(&mut opts);
+    opts.optopt("b", "begin", "first RNG index to include (default 0)", "RNG_CALLS");
+    opts.optopt("n", "count", "number of RNG indices to include", "COUNT");
+    opts.optflag("", "full", "include the full RNG period");
+    opts.optopt("o", "output", "output file (default: STAGE_SPEC.idx; required for bundled specs)", "FILE");
+    crate::format_option(&mut opts, Some("text"));
+    crate::jobs_option(&mut opts);
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} -p PLATFORM -s STAGE (-n COUNT | --full) [OPTIONS]", program);
+    println!("{}", opts.usage(&brief));
+    println!("Precomputes the set at every RNG index in the range. Each index takes 2 bytes");
+    println!("(3 for very large stages), so the full period needs about 8 GiB.");
+    println!("By default the index is written next to the stage-spec file, where");
+    println!("'sa2pg search' picks it up automatically. The bundled specs are part of the");
+    println!("source tree, so with a stage name -o is required and search needs -i.");
+    println!();
+    println!("The text format only reports the file written. The other formats describe it");
+    println!("in one row with the fields path, begin, end (the first index not included),");
+    println!("sets (distinct sets in the index) and bytes.");
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let stage = crate::stage_args(matches)?;
+    let begin = crate::optional(matches, "b")?.unwrap_or(0);
+    let count = if matches.opt_present("full") {
+        FULL_PERIOD
+    }
+    else {
+        crate::optional(matches, "n")?.ok_or_else(|| CliError::usage("missing option: -n COUNT or --full"))?
+    };
+    let jobs = crate::jobs(matches)?;
+    let format = crate::table_format(matches, "text")?;
+    let output_path = match matches.opt_str("o") {
+        Some(path) => path.into(),
+        // Bundled specs live in the source tree, which is no place for a
+        // file of up to 8 GiB.
+        None if stage.bundled => return Err(CliError::usage("missing option: -o FILE (needed for the bundled specs)")),
+        None => set_index::default_path(&stage.spec_path),
+    };
+
+    let output = File::create(&output_path).map_err(CliError::context(&format!("creating {}", output_path.display())))?;
+    with_platform!(stage.platform, stage.sort, build(&stage.spec, begin, count, jobs, output))
+        .map_err(CliError::context("writing set index"))?;
+
+    eprintln!("Wrote {}", output_path.display());
+    if let Some(format) = format {
+        write_summary(&output_path, format).map_err(CliError::context(&format!("reading {}", output_path.display())))?;
+    }
+    Ok(())
+}
+
+fn write_summary(path: &Path, format: Format) -> io::Result<()> {
+    let index = SetIndex::open(path)?;
+    let headers = ["path", "begin", "end", "sets", "bytes"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut table = TableWriter::new(io::stdout().lock(), format, headers)?;
+    table.write_row(&[
+        Cell::Text(path.display().to_string()),
+        Cell::Int(index.begin() as i64),
+        Cell::Int(index.end() as i64),
+        Cell::Int(index.sets().len() as i64),
+        Cell::Int(fs::metadata(path)?.len() as i64),
+    ])?;
+    table.finish()
+}
+
+fn build<P>(spec: &StageSpec, begin: u32, count: u64, jobs: usize, output: File) -> io::Result<()>
+    where P: Platform,
+{
+    set_index::build::<P, _>(spec, begin, count, jobs, output)
+}
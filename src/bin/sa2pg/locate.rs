@@ -0,0 +1,188 @@
+use std::io;
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::constraint::PieceMatch;
+use sa2_piece_gen::hint_lookup::HintLookup;
+use sa2_piece_gen::posterior::{Evidence, Posterior};
+use sa2_piece_gen::stage_spec::{Emerald, StageSpec};
+use sa2_piece_gen::table::{Cell, Format, TableWriter};
+
+use crate::{CliError, CliResult};
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    crate::stage_options(&mut opts);
+    opts.optopt("b", "begin", "first RNG index the set may be at (default 0)", "RNG_CALLS");
+    opts.optopt("e", "end", "RNG index to stop at (default 1000000)", "RNG_CALLS");
+    opts.optmulti("", "seen", "a slot's piece matches a descriptor, e.g. 3=@enemy (repeatable)", "SLOT=PIECES");
+    opts.optmulti("", "hint", "a slot's hint contains this text, e.g. '2=rock' (repeatable)", "SLOT=TEXT");
+    opts.optmulti("", "found", "this piece was found (repeatable)", "PIECE");
+    opts.optmulti("", "empty", "this piece's location was checked and empty (repeatable)", "PIECE");
+    opts.optopt("", "miss", "chance of overlooking a piece at a checked location (default 0)", "RATE");
+    opts.optflag("", "sets", "list the remaining sets instead of locations");
+    opts.optopt("n", "count", "number of sets to list with --sets (default 20)", "N");
+    crate::hint_options(&mut opts);
+    crate::format_option(&mut opts, None);
+    crate::jobs_option(&mut opts);
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} -p PLATFORM -s STAGE [OPTIONS] [P1 P2 P3]", program);
+    println!("{}", opts.usage(&brief));
+    println!("Ranks piece locations by the chance of a piece being there, given what is known");
+    println!("this life, to decide where to look next. Every index in the range is equally");
+    println!("likely to start with. --seen, --hint and --found rule out the sets that");
+    println!("disagree, and --empty rules out the sets with a piece at that location, or");
+    println!("makes them less likely if --miss is above 0.");
+    println!();
+    println!("    sa2pg locate -p gc -s ph -g GAME --hint '3=rock' --empty 0A03 --empty 0705");
+    println!();
+    println!("P1 P2 P3 mark grabbed slots the same way as 'sa2pg search': G0A03 for a piece");
+    println!("grabbed in the previous life, X otherwise. Grabbed slots have no locations.");
+    println!();
+    println!("Fields: piece, x, y, z, probability and hint (the first hint of the piece)");
+    println!("when hints are loaded. With --sets: p1, p2, p3, probability, count (indices");
+    println!("in the range generating the set) and rng_index (the first of them).");
+    crate::print_field_notes();
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let stage = crate::stage_args(matches)?;
+    let lookup = crate::hint_lookup(matches, stage.stage)?;
+    let constraints = crate::table::grabbed_constraints(&matches.free, &stage.spec)?;
+    let grabbed = constraints.grabbed_pieces(&stage.spec);
+    let evidence = evidence(matches, &stage.spec, lookup.as_ref(), grabbed)?;
+
+    let begin = crate::optional(matches, "b")?.unwrap_or(0);
+    let end = crate::optional(matches, "e")?.unwrap_or(1_000_000);
+    if end <= begin {
+        return Err(CliError::usage("the range to search is empty"));
+    }
+    let count = crate::optional(matches, "n")?.unwrap_or(20);
+    let format = crate::format(matches, Format::Csv)?;
+    let jobs = crate::jobs(matches)?;
+
+    let mut posterior = with_platform!(stage.platform, stage.sort, Posterior::new(&stage.spec, grabbed, begin..end, jobs));
+    for evidence in evidence {
+        posterior.observe(evidence);
+    }
+    if posterior.remaining() == 0 {
+        return Err(CliError::failure("no set in the range fits what is known"));
+    }
+
+    let result = if matches.opt_present("sets") {
+        write_sets(&posterior, count, format)
+    }
+    else {
+        write_locations(&posterior, lookup.as_ref(), format)
+    };
+    result.map_err(CliError::context("writing locations"))
+}
+
+/// Gathers the evidence options in the order slots, found, empty.
+pub fn evidence(matches: &Matches, spec: &StageSpec, lookup: Option<&HintLookup>, grabbed: [Option<Emerald>; 3]) -> Result<Vec<Evidence>, CliError> {
+    let mut evidence = Vec::new();
+    for arg in matches.opt_strs("seen") {
+        let (slot, text) = slot_arg(&arg, grabbed)?;
+        let seen = text.parse::<PieceMatch>().map_err(|e| CliError::usage(format!("--seen {}: {}", arg, e)))?;
+        seen.validate(spec).map_err(|e| CliError::usage(format!("--seen {}: {}", arg, e)))?;
+        evidence.push(Evidence::Slot(slot, seen));
+    }
+    for arg in matches.opt_strs("hint") {
+        let (slot, text) = slot_arg(&arg, grabbed)?;
+        let lookup = lookup.ok_or_else(|| CliError::usage("--hint needs -l or -g"))?;
+        let seen = lookup.hint_match(spec, text).map_err(|e| CliError::usage(format!("--hint {}: {}", arg, e)))?;
+        evidence.push(Evidence::Slot(slot, seen));
+    }
+    for arg in matches.opt_strs("found") {
+        evidence.push(Evidence::Found(piece_arg(&arg, spec)?));
+    }
+    let miss_rate: f64 = crate::optional(matches, "miss")?.unwrap_or(0.0);
+    if !(0.0..=1.0).contains(&miss_rate) {
+        return Err(CliError::usage("--miss has to be between 0 and 1"));
+    }
+    for arg in matches.opt_strs("empty") {
+        evidence.push(Evidence::Empty { id: piece_arg(&arg, spec)?, miss_rate });
+    }
+    Ok(evidence)
+}
+
+/// Parses `SLOT=VALUE` with a slot that isn't grabbed.
+fn slot_arg(arg: &str, grabbed: [Option<Emerald>; 3]) -> Result<(usize, &str), CliError> {
+    let (slot, value) = arg.split_once('=')
+        .ok_or_else(|| CliError::usage(format!("expected SLOT=VALUE, got '{}'", arg)))?;
+    let slot = parse_slot(slot.trim(), grabbed.map(|piece| piece.is_some())).map_err(CliError::usage)?;
+    Ok((slot, value))
+}
+
+fn piece_arg(arg: &str, spec: &StageSpec) -> Result<u16, CliError> {
+    parse_piece(arg, spec).map_err(CliError::usage)
+}
+
+/// Parses a slot from 1 to 3, or p1 to p3, that isn't grabbed. Slots count
+/// from 0 in the result.
+pub fn parse_slot(slot: &str, grabbed: [bool; 3]) -> Result<usize, String> {
+    let slot = match slot.trim_start_matches(['p', 'P']) {
+        "1" => 0,
+        "2" => 1,
+        "3" => 2,
+        _ => return Err(format!("bad slot '{}', expected 1, 2 or 3", slot)),
+    };
+    if grabbed[slot] {
+        return Err(format!("p{} was grabbed in an earlier life", slot + 1));
+    }
+    Ok(slot)
+}
+
+/// Parses the hexadecimal ID of a piece in the stage.
+pub fn parse_piece(arg: &str, spec: &StageSpec) -> Result<u16, String> {
+    let id = u16::from_str_radix(arg, 16).map_err(|e| format!("bad piece '{}': {}", arg, e))?;
+    if spec.get_emerald_by_id(id).is_none() {
+        return Err(format!("piece {:04X} is not present in stage", id));
+    }
+    Ok(id)
+}
+
+fn write_locations(posterior: &Posterior, lookup: Option<&HintLookup>, format: Format) -> io::Result<()> {
+    let mut headers: Vec<String> = ["piece", "x", "y", "z", "probability"].iter().map(|s| s.to_string()).collect();
+    if lookup.is_some() {
+        headers.push("hint".to_string());
+    }
+
+    let stdout = io::stdout();
+    let mut table = TableWriter::new(stdout.lock(), format, headers)?;
+    for location in posterior.locations() {
+        let piece = location.piece;
+        let mut row = vec![
+            Cell::Text(format!("{:04X}", piece.id)),
+            Cell::Float(piece.position.x),
+            Cell::Float(piece.position.y),
+            Cell::Float(piece.position.z),
+            Cell::Float(location.probability as f32),
+        ];
+        if let Some(hints) = lookup {
+            row.push(Cell::Text(hints.lookup_piece(piece.id).h1.replace('\n', " ")));
+        }
+        table.write_row(&row)?;
+    }
+    table.finish()
+}
+
+fn write_sets(posterior: &Posterior, count: usize, format: Format) -> io::Result<()> {
+    let headers = ["p1", "p2", "p3", "probability", "count", "rng_index"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let stdout = io::stdout();
+    let mut table = TableWriter::new(stdout.lock(), format, headers)?;
+    for (set, probability) in posterior.sets().into_iter().take(count) {
+        let mut row: Vec<Cell> = set.ids().iter().map(|id| Cell::Text(format!("{:04X}", id))).collect();
+        row.push(Cell::Float(probability as f32));
+        row.push(Cell::Int(set.count as i64));
+        row.push(Cell::Int(set.first_index as i64));
+        table.write_row(&row)?;
+    }
+    table.finish()
+}
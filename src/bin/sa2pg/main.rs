@@ -0,0 +1,471 @@
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::game_files::{self, HintLanguage, Stage};
+use sa2_piece_gen::hint_lookup::HintLookup;
+use sa2_piece_gen::parallel;
+use sa2_piece_gen::stage_spec::StageSpec;
+use sa2_piece_gen::table::Format;
+
+/// Runs a generic function with the platform type picked on the command line,
+/// and the sort picked with --sort if given.
+macro_rules! with_platform {
+    ($platform:expr, $($func:ident)::+($($arg:expr),* $(,)?)) => {
+        with_platform!($platform, crate::SortArg::Stable, $($func)::+($($arg),*))
+    };
+    ($platform:expr, $sort:expr, $($func:ident)::+($($arg:expr),* $(,)?)) => {
+        match $platform {
+            crate::PlatformArg::Pc => with_sort!(sa2_piece_gen::Pc, $sort, $($func)::+($($arg),*)),
+            crate::PlatformArg::PcX87Single => with_sort!(sa2_piece_gen::PcX87<{ sa2_piece_gen::x87::SINGLE }>, $sort, $($func)::+($($arg),*)),
+            crate::PlatformArg::PcX87Double => with_sort!(sa2_piece_gen::PcX87<{ sa2_piece_gen::x87::DOUBLE }>, $sort, $($func)::+($($arg),*)),
+            crate::PlatformArg::PcX87Extended => with_sort!(sa2_piece_gen::PcX87<{ sa2_piece_gen::x87::EXTENDED }>, $sort, $($func)::+($($arg),*)),
+            crate::PlatformArg::Gc => with_sort!(sa2_piece_gen::Gc, $sort, $($func)::+($($arg),*)),
+        }
+    };
+}
+
+macro_rules! with_sort {
+    ($platform:ty, $sort:expr, $($func:ident)::+($($arg:expr),*)) => {
+        match $sort {
+            crate::SortArg::Stable => $($func)::+::<$platform>($($arg),*),
+            crate::SortArg::Msvc => $($func)::+::<sa2_piece_gen::WithSort<$platform, sa2_piece_gen::sort::MsvcQsort>>($($arg),*),
+            crate::SortArg::Msl => $($func)::+::<sa2_piece_gen::WithSort<$platform, sa2_piece_gen::sort::MslQsort>>($($arg),*),
+        }
+    };
+}
+
+mod calibrate;
+mod dump;
+mod explain;
+mod hunt;
+mod identify;
+mod index;
+mod locate;
+mod odds;
+mod precalls;
+mod reverse;
+mod search;
+mod sensitivity;
+mod table;
+
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+struct Command {
+    name: &'static str,
+    about: &'static str,
+    options: fn() -> Options,
+    usage: fn(&str, Options),
+    run: fn(&Matches) -> CliResult,
+}
+
+const COMMANDS: &[Command] = &[
+    Command { name: "search", about: "find RNG indices that generate matching sets", options: search::options, usage: search::print_usage, run: search::run },
+    Command { name: "table", about: "print the set at each RNG call after the pre-calls", options: table::options, usage: table::print_usage, run: table::run },
+    Command { name: "identify", about: "find the RNG index from what was seen over several lives", options: identify::options, usage: identify::print_usage, run: identify::run },
+    Command { name: "explain", about: "show each step of generating the set at one RNG index", options: explain::options, usage: explain::print_usage, run: explain::run },
+    Command { name: "locate", about: "rank where pieces are likely to be given what was checked", options: locate::options, usage: locate::print_usage, run: locate::run },
+    Command { name: "hunt", about: "track hints and checked locations interactively during a run", options: hunt::options, usage: hunt::print_usage, run: hunt::run },
+    Command { name: "odds", about: "count how often each piece shows up over a range", options: odds::options, usage: odds::print_usage, run: odds::run },
+    Command { name: "sensitivity", about: "flag RNG indices whose set depends on float precision", options: sensitivity::options, usage: sensitivity::print_usage, run: sensitivity::run },
+    Command { name: "calibrate", about: "find a stage's pre-calls from sets seen after loading it", options: calibrate::options, usage: calibrate::print_usage, run: calibrate::run },
+    Command { name: "precalls", about: "add up a stage's pre-calls under a load model of its objects", options: precalls::options, usage: precalls::print_usage, run: precalls::run },
+    Command { name: "index", about: "precompute a set index for fast searches", options: index::options, usage: index::print_usage, run: index::run },
+    Command { name: "dump", about: "write a stage spec from a SET file, game files or memory", options: dump::options, usage: dump::print_usage, run: dump::run },
+    Command { name: "reverse", about: "find the RNG index of an RNG state", options: reverse::options, usage: reverse::print_usage, run: reverse::run },
+];
+
+/// Errors that end a subcommand. Usage errors exit with 2, everything else
+/// with 1.
+#[derive(Debug)]
+pub enum CliError {
+    Usage(String),
+    Failure(String),
+}
+
+pub type CliResult = Result<(), CliError>;
+
+impl CliError {
+    pub fn usage<S: Into<String>>(msg: S) -> CliError {
+        CliError::Usage(msg.into())
+    }
+
+    pub fn failure<S: Into<String>>(msg: S) -> CliError {
+        CliError::Failure(msg.into())
+    }
+
+    /// Adds what was being done to an error's message.
+    pub fn context<E: fmt::Display>(what: &str) -> impl FnOnce(E) -> CliError + '_ {
+        move |e| CliError::Failure(format!("{}: {}", what, e))
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Usage(msg) | CliError::Failure(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(e: io::Error) -> CliError {
+        CliError::Failure(e.to_string())
+    }
+}
+
+impl From<getopts::Fail> for CliError {
+    fn from(e: getopts::Fail) -> CliError {
+        CliError::Usage(e.to_string())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlatformArg {
+    Pc,
+    /// PC with the x87 math model at 24, 53 or 64 bits of precision.
+    PcX87Single,
+    PcX87Double,
+    PcX87Extended,
+    Gc,
+}
+
+impl PlatformArg {
+    pub fn name(self) -> &'static str {
+        match self {
+            PlatformArg::Pc => "pc",
+            PlatformArg::PcX87Single => "pc-x87-24",
+            PlatformArg::PcX87Double => "pc-x87-53",
+            PlatformArg::PcX87Extended => "pc-x87-64",
+            PlatformArg::Gc => "gc",
+        }
+    }
+
+    /// The version of the game, which picks the stage specs.
+    pub fn game_version(self) -> &'static str {
+        match self {
+            PlatformArg::Gc => "gc",
+            _ => "pc",
+        }
+    }
+}
+
+impl FromStr for PlatformArg {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<PlatformArg, CliError> {
+        match s.to_ascii_lowercase().as_str() {
+            "pc" => Ok(PlatformArg::Pc),
+            "pc-x87-24" => Ok(PlatformArg::PcX87Single),
+            "pc-x87-53" => Ok(PlatformArg::PcX87Double),
+            "pc-x87" | "pc-x87-64" => Ok(PlatformArg::PcX87Extended),
+            "gc" => Ok(PlatformArg::Gc),
+            _ => Err(CliError::usage(format!("unknown platform '{}' (expected pc, pc-x87[-24|-53|-64] or gc)", s))),
+        }
+    }
+}
+
+/// The sort ties between candidates are broken with; see `sa2_piece_gen::sort`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortArg {
+    #[default]
+    Stable,
+    Msvc,
+    Msl,
+}
+
+impl FromStr for SortArg {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<SortArg, CliError> {
+        match s.to_ascii_lowercase().as_str() {
+            "stable" => Ok(SortArg::Stable),
+            "msvc" => Ok(SortArg::Msvc),
+            "msl" => Ok(SortArg::Msl),
+            _ => Err(CliError::usage(format!("unknown sort '{}' (expected stable, msvc or msl)", s))),
+        }
+    }
+}
+
+/// Adds the options every stage-based subcommand shares.
+pub fn stage_options(opts: &mut Options) {
+    opts.optopt("p", "platform", "platform to simulate: pc, gc, or pc-x87-24, -53 or -64 for PC with x87 math at that precision (pc-x87 is -64)", "PLATFORM");
+    opts.optopt("s", "stage", "stage-spec file or stage name", "STAGE");
+    opts.optopt("", "sort", "how candidates with equal keys are ordered: stable, or msvc or msl for the qsort of the PC or GC C library, unconfirmed (default stable)", "SORT");
+    opts.optopt("", "specs", "directory holding PC/ and GC/ stage specs (default: bundled)", "DIR");
+    opts.optopt("", "entry", "entry path with its own offset in the spec (e.g. story, stage-select, restart, retry)", "NAME");
+}
+
+/// Adds the options for loading hints.
+pub fn hint_options(opts: &mut Options) {
+    opts.optopt("l", "lookup", "include hints from this PRS file, game directory or disc image", "FILE");
+    opts.optopt("g", "game", "PC install, extracted GC disc or GC disc image to find stage files in", "DIR");
+    opts.optopt("", "lang", "hint language (j, e, f, s, g, i; default e)", "LANG");
+}
+
+/// Adds -f for the table formats. `own` names a format of the command's own
+/// (e.g. "text") offered besides them, which is then the default.
+pub fn format_option(opts: &mut Options, own: Option<&str>) {
+    let help = match own {
+        Some(own) => format!("output format: {}, csv, tsv, json, ndjson or md (default {})", own, own),
+        None => "output format: csv, tsv, json, ndjson or md (default csv)".to_string(),
+    };
+    opts.optopt("f", "format", &help, "FORMAT");
+}
+
+/// Notes on the output fields shared by every command's usage text.
+pub fn print_field_notes() {
+    println!("Piece IDs and RNG states are hexadecimal strings (e.g. \"0A03\", \"DEAD0CAB\"),");
+    println!("a grabbed slot shows FE00 and its hints N/A. json writes one array of objects,");
+    println!("ndjson one object per line; csv and tsv start with a header row.");
+}
+
+pub fn format(matches: &Matches, default: Format) -> Result<Format, CliError> {
+    Ok(optional(matches, "f")?.unwrap_or(default))
+}
+
+/// The table format picked with -f, or `None` for the command's `own` format.
+pub fn table_format(matches: &Matches, own: &str) -> Result<Option<Format>, CliError> {
+    match matches.opt_str("f") {
+        None => Ok(None),
+        Some(ref name) if name.eq_ignore_ascii_case(own) => Ok(None),
+        Some(_) => format(matches, Format::Csv).map(Some),
+    }
+}
+
+pub fn jobs_option(opts: &mut Options) {
+    opts.optopt("j", "jobs", "number of threads (default: one per CPU)", "N");
+}
+
+/// A stage spec picked with the shared stage options.
+pub struct StageArgs {
+    pub platform: PlatformArg,
+    pub sort: SortArg,
+    pub stage: Option<Stage>,
+    pub spec_path: PathBuf,
+    /// The spec, with `pre_calls` for the entry path picked with --entry.
+    pub spec: StageSpec,
+    pub entry: Option<String>,
+    /// `pre_calls` as stored in the spec file.
+    pub base_pre_calls: u32,
+    /// Whether the spec is one of the bundled ones, picked by stage name
+    /// without --specs.
+    pub bundled: bool,
+}
+
+impl StageArgs {
+    /// Refuses to write to the bundled specs, which live in the source tree.
+    pub fn check_writable(&self) -> Result<(), CliError> {
+        if self.bundled {
+            return Err(CliError::usage(format!("refusing to write to the bundled spec {}; pass a spec file with -s or a directory with --specs", self.spec_path.display())));
+        }
+        Ok(())
+    }
+
+    /// Writes a new `pre_calls` for the picked entry path to the spec file:
+    /// the base value without --entry, the entry's offset with it.
+    pub fn save_pre_calls(&self, pre_calls: u32) -> io::Result<()> {
+        let mut spec = self.spec.clone();
+        spec.pre_calls = self.base_pre_calls;
+        match self.entry {
+            Some(ref entry) => {
+                spec.entry_offsets.insert(entry.clone(), pre_calls.wrapping_sub(self.base_pre_calls) as i32);
+            }
+            None => spec.pre_calls = pre_calls,
+        }
+        write_spec(&spec, &self.spec_path)
+    }
+
+    /// " for entry path NAME" if --entry was given, for messages.
+    pub fn entry_note(&self) -> String {
+        self.entry.as_ref().map(|entry| format!(" for entry path {}", entry)).unwrap_or_default()
+    }
+}
+
+pub fn stage_args(matches: &Matches) -> Result<StageArgs, CliError> {
+    load_stage(matches, false)
+}
+
+/// Like `stage_args`, for commands that write pre-calls back: an --entry the
+/// spec has no offset for yet starts out at the base pre-calls.
+pub fn stage_args_to_update(matches: &Matches) -> Result<StageArgs, CliError> {
+    load_stage(matches, true)
+}
+
+fn load_stage(matches: &Matches, new_entry: bool) -> Result<StageArgs, CliError> {
+    let platform = required::<PlatformArg>(matches, "p")?;
+    let stage_arg = required::<String>(matches, "s")?;
+    load_spec(platform, sort(matches)?, &stage_arg, matches.opt_str("specs").as_deref(), matches.opt_str("entry"), new_entry)
+}
+
+/// Loads a stage spec like the shared stage options do, for commands that
+/// pick stages some other way.
+pub fn load_spec(platform: PlatformArg, sort: SortArg, stage_arg: &str, specs: Option<&str>, entry: Option<String>, new_entry: bool) -> Result<StageArgs, CliError> {
+    let spec_path = game_files::resolve_spec(stage_arg, platform.game_version(), specs)
+        .map_err(CliError::context("finding stage spec"))?;
+    let file = File::open(&spec_path).map_err(CliError::context(&format!("opening {}", spec_path.display())))?;
+    let mut spec: StageSpec = serde_json::from_reader(file).map_err(CliError::context(&format!("reading {}", spec_path.display())))?;
+
+    let base_pre_calls = spec.pre_calls;
+    let bundled = specs.is_none() && !Path::new(stage_arg).is_file();
+    if let Some(ref entry) = entry {
+        match spec.entry_pre_calls(entry) {
+            Some(pre_calls) => spec.pre_calls = pre_calls,
+            None if new_entry => {}
+            None => {
+                let known: Vec<&str> = spec.entry_offsets.keys().map(|name| name.as_str()).collect();
+                let known = if known.is_empty() { "none".to_string() } else { known.join(", ") };
+                return Err(CliError::usage(format!("{} has no entry path '{}' (known: {})", spec_path.display(), entry, known)));
+            }
+        }
+    }
+
+    Ok(StageArgs {
+        platform,
+        sort,
+        stage: Stage::from_name(stage_arg),
+        spec_path,
+        spec,
+        entry,
+        base_pre_calls,
+        bundled,
+    })
+}
+
+/// Overwrites a stage spec file, in the layout of the bundled ones.
+pub fn write_spec(spec: &StageSpec, path: &Path) -> io::Result<()> {
+    let mut output = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut output, spec)?;
+    output.flush()
+}
+
+/// Loads hints if `-l` or `-g` was given.
+pub fn hint_lookup(matches: &Matches, stage: Option<Stage>) -> Result<Option<HintLookup>, CliError> {
+    let lang = hint_language(matches)?;
+    match matches.opt_str("l").or_else(|| matches.opt_str("g")) {
+        Some(arg) => game_files::load_hints(&arg, stage, lang)
+            .map(Some)
+            .map_err(CliError::context("loading hints")),
+        None => Ok(None),
+    }
+}
+
+pub fn hint_language(matches: &Matches) -> Result<HintLanguage, CliError> {
+    match matches.opt_str("lang") {
+        Some(code) => HintLanguage::from_code(&code)
+            .ok_or_else(|| CliError::usage(format!("unknown hint language '{}'", code))),
+        None => Ok(HintLanguage::default()),
+    }
+}
+
+pub fn jobs(matches: &Matches) -> Result<usize, CliError> {
+    Ok(optional(matches, "j")?.unwrap_or_else(parallel::default_jobs))
+}
+
+pub fn sort(matches: &Matches) -> Result<SortArg, CliError> {
+    Ok(optional(matches, "sort")?.unwrap_or_default())
+}
+
+/// Parses an option's value, if present.
+pub fn optional<T>(matches: &Matches, name: &str) -> Result<Option<T>, CliError>
+    where T: FromStr,
+          T::Err: fmt::Display,
+{
+    matches.opt_str(name)
+        .map(|value| value.parse().map_err(|e| CliError::usage(format!("invalid value '{}' for {}: {}", value, option_name(name), e))))
+        .transpose()
+}
+
+pub fn required<T>(matches: &Matches, name: &str) -> Result<T, CliError>
+    where T: FromStr,
+          T::Err: fmt::Display,
+{
+    optional(matches, name)?.ok_or_else(|| CliError::usage(format!("missing option: {}", option_name(name))))
+}
+
+fn option_name(name: &str) -> String {
+    if name.len() == 1 { format!("-{}", name) } else { format!("--{}", name) }
+}
+
+/// Parses an RNG state given in hexadecimal, with or without a 0x prefix.
+pub fn parse_state(s: &str) -> Result<u32, CliError> {
+    u32::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|e| CliError::usage(format!("invalid RNG state '{}': {}", s, e)))
+}
+
+fn print_help() {
+    println!("Usage: sa2pg COMMAND [OPTIONS]");
+    println!();
+    println!("Commands:");
+    for command in COMMANDS {
+        println!("    {:<13}{}", command.name, command.about);
+    }
+    println!("    help         print help for a command");
+    println!();
+    println!("Run 'sa2pg COMMAND -h' for the options of a command.");
+    println!();
+    println!("Exit codes: 0 on success, 1 if the command failed, 2 on invalid usage.");
+}
+
+fn run(args: &[String]) -> Result<(), CliError> {
+    let name = match args.first() {
+        Some(name) => name.as_str(),
+        None => {
+            print_help();
+            return Err(CliError::usage("no command given"));
+        }
+    };
+
+    if name == "help" || name == "-h" || name == "--help" {
+        match args.get(1).map(|name| find_command(name)).transpose()? {
+            Some(command) => (command.usage)(&format!("sa2pg {}", command.name), command_options(command)),
+            None => print_help(),
+        }
+        return Ok(());
+    }
+
+    let command = find_command(name)?;
+    let opts = command_options(command);
+    let matches = opts.parse(&args[1..])?;
+    if matches.opt_present("h") {
+        (command.usage)(&format!("sa2pg {}", command.name), opts);
+        return Ok(());
+    }
+
+    (command.run)(&matches)
+}
+
+fn command_options(command: &Command) -> Options {
+    let mut opts = (command.options)();
+    opts.optflag("h", "help", "print this help menu");
+    opts
+}
+
+fn find_command(name: &str) -> Result<&'static Command, CliError> {
+    COMMANDS.iter()
+        .find(|command| command.name == name)
+        .ok_or_else(|| CliError::usage(format!("unknown command '{}'", name)))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Err(e) = run(&args) {
+        let command = args.first().map(|name| format!(" {}", name)).unwrap_or_default();
+        eprintln!("sa2pg{}: error: {}", command, e);
+        process::exit(match e {
+            CliError::Usage(_) => {
+                eprintln!("Run 'sa2pg help{}' for usage.", command);
+                EXIT_USAGE
+            }
+            CliError::Failure(_) => EXIT_FAILURE,
+        });
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::candidate_cache::CandidateCache;
+use sa2_piece_gen::constraint::{SetConstraints, GRABBED_ID};
+use sa2_piece_gen::hint_lookup::HintLookup;
+use sa2_piece_gen::parallel;
+use sa2_piece_gen::stage_spec::StageSpec;
+use sa2_piece_gen::table::{Cell, Format, TableWriter};
+use sa2_piece_gen::Platform;
+
+use crate::{CliError, CliResult};
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    crate::stage_options(&mut opts);
+    opts.optopt("b", "begin", "first RNG index to count (default 0)", "RNG_CALLS");
+    opts.optopt("e", "end", "RNG index to stop at (default 1000000)", "RNG_CALLS");
+    crate::hint_options(&mut opts);
+    crate::format_option(&mut opts, None);
+    crate::jobs_option(&mut opts);
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} -p PLATFORM -s STAGE [OPTIONS] [P1 P2 P3]", program);
+    println!("{}", opts.usage(&brief));
+    println!("Counts how often each piece is picked for each slot over a range of RNG indices,");
+    println!("e.g. to see what comes up after dying with a piece grabbed.");
+    println!();
+    println!("P1 P2 P3 mark grabbed slots the same way as 'sa2pg search': G0A03 for a piece");
+    println!("grabbed in the previous life, X otherwise. Grabbed slots are left out.");
+    println!();
+    println!("Fields: slot (1-3), piece, count, percent of the range, and hint (the first");
+    println!("hint of the piece) when hints are loaded. Rows are sorted by slot, then by count.");
+    crate::print_field_notes();
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let stage = crate::stage_args(matches)?;
+    let lookup = crate::hint_lookup(matches, stage.stage)?;
+    let constraints = crate::table::grabbed_constraints(&matches.free, &stage.spec)?;
+    let begin = crate::optional::<u32>(matches, "b")?.unwrap_or(0) as u64;
+    let end = crate::optional::<u32>(matches, "e")?.unwrap_or(1_000_000) as u64;
+    if end <= begin {
+        return Err(CliError::usage("the range to count is empty"));
+    }
+    let format = crate::format(matches, Format::Csv)?;
+    let jobs = crate::jobs(matches)?;
+
+    let counts = with_platform!(stage.platform, stage.sort, count_pieces(&stage.spec, begin, end, jobs, &constraints));
+    write_odds(&counts, end - begin, lookup.as_ref(), format)
+        .map_err(CliError::context("writing odds"))
+}
+
+/// Per slot, how many sets in the range had each piece.
+fn count_pieces<P>(spec: &StageSpec, begin: u64, end: u64, jobs: usize, constraints: &SetConstraints) -> [BTreeMap<u16, u64>; 3]
+    where P: Platform,
+{
+    let cache = CandidateCache::<P>::with_grabbed(spec, constraints.grabbed_pieces(spec));
+    let mut counts = [BTreeMap::new(), BTreeMap::new(), BTreeMap::new()];
+
+    parallel::scan::<P::Consts, _, _, _>(begin, end, jobs, |mut r| Some(cache.gen_pieces(&mut r)), |_, pieces| {
+        for (slot, piece) in pieces.iter().enumerate() {
+            if piece.id != GRABBED_ID {
+                *counts[slot].entry(piece.id).or_insert(0) += 1;
+            }
+        }
+    });
+
+    counts
+}
+
+fn write_odds(counts: &[BTreeMap<u16, u64>; 3], total: u64, lookup: Option<&HintLookup>, format: Format) -> io::Result<()> {
+    let mut headers: Vec<String> = ["slot", "piece", "count", "percent"].iter().map(|s| s.to_string()).collect();
+    if lookup.is_some() {
+        headers.push("hint".to_string());
+    }
+
+    let stdout = io::stdout();
+    let mut table = TableWriter::new(stdout.lock(), format, headers)?;
+    for (slot, slot_counts) in counts.iter().enumerate() {
+        let mut slot_counts: Vec<_> = slot_counts.iter().collect();
+        slot_counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        for (&id, &count) in slot_counts {
+            let mut row = vec![
+                Cell::Int(slot as i64 + 1),
+                Cell::Text(format!("{:04X}", id)),
+                Cell::Int(count as i64),
+                Cell::Float(((count as f64 * 100_000.0 / total as f64).round() / 1000.0) as f32),
+            ];
+            if let Some(hints) = lookup {
+                row.push(Cell::Text(hints.lookup_piece(id).h1.replace('\n', " ")));
+            }
+            table.write_row(&row)?;
+        }
+    }
+    table.finish()
+}
@@ -0,0 +1,138 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+
+use getopts::{Matches, Options};
+use sa2_set::SetFile;
+
+use sa2_piece_gen::game_files::{GameFiles, Stage};
+use sa2_piece_gen::load_model::{self, CallCount, LoadModel};
+use sa2_piece_gen::set_data;
+use sa2_piece_gen::table::{Cell, Format, TableWriter};
+
+use crate::{CliError, CliResult};
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    crate::stage_options(&mut opts);
+    opts.optopt("m", "model", "JSON file with the RNG calls of each object type", "FILE");
+    opts.optopt("g", "game", "read the stage's SET files from this game directory or disc image", "DIR");
+    opts.optmulti("", "set", "read this SET file instead (raw or PRS-compressed; repeatable)", "FILE");
+    opts.optflag("w", "write", "write the pre-calls to the stage spec file given with -s or --specs");
+    opts.optflag("", "force", "write with -w even if some object types are not in the model");
+    crate::format_option(&mut opts, Some("text"));
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} -p PLATFORM -s STAGE -m MODEL (-g DIR | --set FILE...) [OPTIONS]", program);
+    println!("{}", opts.usage(&brief));
+    println!("Adds up the stage's pre-calls under a load model of the objects in its SET");
+    println!("files: the model's base calls plus the calls of every object loaded. The");
+    println!("model is JSON like");
+    println!();
+    println!("    {{\"base_calls\": 120, \"objects\": [{{\"object\": 56, \"calls\": 1}},");
+    println!("                                    {{\"object\": 83, \"level\": 25, \"calls\": 2}}]}}");
+    println!();
+    println!("where an entry with a level number only applies to that level and overrides");
+    println!("one without. Object types missing from the model are counted as no calls and");
+    println!("marked as unknown. No model is bundled, as per-object counts haven't been");
+    println!("measured yet, so the result is only as good as the model given. -s also");
+    println!("picks the spec the result is compared with, and");
+    println!("-w writes it there, as the offset of the entry path if --entry is given. -w");
+    println!("needs -s to name a spec file or --specs a directory, so the bundled specs are");
+    println!("never overwritten. It also refuses while any object type is unknown, unless");
+    println!("--force is given.");
+    println!();
+    println!("The text format prints a summary and the object types. The other formats have");
+    println!("the fields object (hexadecimal ID), count, calls_each (N/A if unknown) and");
+    println!("calls.");
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let stage = crate::stage_args_to_update(matches)?;
+    if matches.opt_present("w") {
+        stage.check_writable()?;
+    }
+    let model = load_model(&crate::required::<String>(matches, "m")?)?;
+    let sets = read_sets(matches, stage.stage)?;
+    let calls = model.count_calls(stage.stage.map(|stage| stage.level_id()), load_model::object_ids(&sets));
+
+    match crate::table_format(matches, "text")? {
+        None => print_report(&calls, &model, stage.spec.pre_calls)?,
+        Some(format) => write_table(&calls, format)?,
+    }
+
+    if matches.opt_present("w") {
+        let unknown = calls.unknown().count();
+        if unknown > 0 && !matches.opt_present("force") {
+            return Err(CliError::failure(format!("not writing: {} object types are not in the model; add them or pass --force", unknown)));
+        }
+        stage.save_pre_calls(calls.pre_calls)
+            .map_err(CliError::context(&format!("writing {}", stage.spec_path.display())))?;
+        eprintln!("Wrote pre-calls {}{} to {}", calls.pre_calls, stage.entry_note(), stage.spec_path.display());
+    }
+    Ok(())
+}
+
+pub fn load_model(path: &str) -> Result<LoadModel, CliError> {
+    let file = File::open(path).map_err(CliError::context(&format!("opening {}", path)))?;
+    serde_json::from_reader(file).map_err(CliError::context(&format!("reading {}", path)))
+}
+
+/// Reads the SET files given with --set, or those of the stage from -g.
+pub fn read_sets(matches: &Matches, stage: Option<Stage>) -> Result<Vec<SetFile>, CliError> {
+    let paths = matches.opt_strs("set");
+    let data = match (matches.opt_str("g"), paths.is_empty()) {
+        (Some(dir), true) => {
+            let stage = stage.ok_or_else(|| CliError::usage("-g needs -s to be a stage name"))?;
+            let game = GameFiles::open(&dir).map_err(CliError::context(&format!("opening {}", dir)))?;
+            game.read_sets(stage).map_err(CliError::context("reading SET files"))?
+        }
+        (None, false) => paths.iter()
+            .map(|path| fs::read(path).map_err(CliError::context(&format!("reading {}", path))))
+            .collect::<Result<_, _>>()?,
+        _ => return Err(CliError::usage("expected exactly one of -g or --set")),
+    };
+
+    data.into_iter()
+        .map(|data| set_data::read_set_file(data.as_slice()).map_err(CliError::context("reading SET file")))
+        .collect()
+}
+
+fn print_report(calls: &CallCount, model: &LoadModel, current: u32) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "pre-calls under the model {} (spec has {}, base calls {})", calls.pre_calls, current, model.base_calls)?;
+    writeln!(out, "object  count  calls")?;
+    for tally in &calls.objects {
+        let total = match tally.calls_each {
+            Some(_) => tally.calls().to_string(),
+            None => "?".to_string(),
+        };
+        writeln!(out, "  {:04X}  {:>5}  {:>5}", tally.object, tally.count, total)?;
+    }
+
+    let unknown = calls.unknown().count();
+    if unknown > 0 {
+        writeln!(out, "{} of {} object types are not in the model and count as no calls", unknown, calls.objects.len())?;
+    }
+    Ok(())
+}
+
+fn write_table(calls: &CallCount, format: Format) -> io::Result<()> {
+    let headers = ["object", "count", "calls_each", "calls"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let stdout = io::stdout();
+    let mut table = TableWriter::new(stdout.lock(), format, headers)?;
+    for tally in &calls.objects {
+        table.write_row(&[
+            Cell::Text(format!("{:04X}", tally.object)),
+            Cell::Int(tally.count as i64),
+            tally.calls_each.map_or_else(|| Cell::Text("N/A".to_string()), |calls| Cell::Int(calls as i64)),
+            Cell::Int(tally.calls() as i64),
+        ])?;
+    }
+    table.finish()
+}
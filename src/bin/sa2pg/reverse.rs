@@ -0,0 +1,63 @@
+use std::io::{self, Write};
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::rng::Rng;
+use sa2_piece_gen::table::{Cell, TableWriter};
+use sa2_piece_gen::Platform;
+
+use crate::{CliError, CliResult, PlatformArg};
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    opts.optopt("p", "platform", "platform to simulate: pc, pc-x87[-24|-53|-64] or gc", "PLATFORM");
+    crate::format_option(&mut opts, Some("text"));
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} -p PLATFORM STATE...", program);
+    println!("{}", opts.usage(&brief));
+    println!("Prints the number of RNG calls from the boot seed to each hexadecimal STATE.");
+    println!();
+    println!("The text format prints one index per line. The other formats have the fields");
+    println!("state and rng_index.");
+    crate::print_field_notes();
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let platform = crate::required::<PlatformArg>(matches, "p")?;
+    if matches.free.is_empty() {
+        return Err(CliError::usage("expected at least one RNG state"));
+    }
+
+    let states = matches.free.iter()
+        .map(|state| crate::parse_state(state))
+        .collect::<Result<Vec<_>, _>>()?;
+    let format = crate::table_format(matches, "text")?;
+
+    let stdout = io::stdout();
+    match format {
+        None => {
+            let mut stdout = stdout.lock();
+            for &state in &states {
+                writeln!(stdout, "{}", with_platform!(platform, index_of(state)))?;
+            }
+        }
+        Some(format) => {
+            let mut table = TableWriter::new(stdout.lock(), format, vec!["state".to_string(), "rng_index".to_string()])?;
+            for &state in &states {
+                let index = with_platform!(platform, index_of(state));
+                table.write_row(&[Cell::Text(format!("{:08X}", state)), Cell::Int(index as i64)])?;
+            }
+            table.finish()?;
+        }
+    }
+    Ok(())
+}
+
+fn index_of<P>(state: u32) -> u32
+    where P: Platform,
+{
+    Rng::index_of::<P::Consts>(state)
+}
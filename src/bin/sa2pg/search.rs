@@ -0,0 +1,352 @@
+use std::fs::File;
+use std::io::{self, BufReader, StdoutLock, Write};
+use std::path::{Path, PathBuf};
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::candidate_cache::CandidateCache;
+use sa2_piece_gen::constraint::{self, GeometryConstraint, PieceConstraint, SetConstraints, SetPredicate, GRABBED_ID};
+use sa2_piece_gen::hint_lookup::HintLookup;
+use sa2_piece_gen::parallel::{self, FULL_PERIOD};
+use sa2_piece_gen::rng::Rng;
+use sa2_piece_gen::set_index::{self, SetIndex};
+use sa2_piece_gen::stage_spec::{Emerald, StageSpec};
+use sa2_piece_gen::table::{Cell, Format, SetColumns, TableWriter};
+use sa2_piece_gen::Platform;
+
+use crate::{CliError, CliResult};
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    crate::stage_options(&mut opts);
+    opts.optopt("b", "begin", "first RNG index to search (default 0)", "RNG_CALLS");
+    opts.optopt("e", "end", "RNG index to stop at (default: end of the period)", "RNG_CALLS");
+    crate::hint_options(&mut opts);
+    opts.optmulti("", "none", "reject sets where any generated piece matches", "PIECES");
+    opts.optmulti("", "some", "require at least one generated piece to match", "PIECES");
+    opts.optopt("", "max-gap", "maximum distance between consecutive pieces", "DIST");
+    opts.optopt("", "max-path", "maximum route length through all pieces", "DIST");
+    opts.optopt("", "from", "start point of the --max-path route", "X,Y,Z");
+    opts.optopt("", "within", "require all pieces within this distance of --center", "RADIUS");
+    opts.optopt("", "center", "center point for --within", "X,Y,Z");
+    opts.optopt("", "near", "find the matches closest to this RNG index", "RNG_CALLS");
+    opts.optopt("", "near-state", "find the matches closest to this RNG state", "HEX_STATE");
+    opts.optopt("n", "count", "matches to find in each direction with --near (default 5)", "N");
+    opts.optopt("", "max-distance", "how far --near looks in each direction (default 1000000)", "RNG_CALLS");
+    crate::format_option(&mut opts, Some("text"));
+    opts.optopt("i", "index", "read sets from this index (default: STAGE_SPEC.idx if present)", "FILE");
+    opts.optflag("", "no-index", "always generate sets, even if an index is present");
+    crate::jobs_option(&mut opts);
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} -p PLATFORM -s STAGE [OPTIONS] P1 P2 P3", program);
+    println!("{}", opts.usage(&brief));
+    println!("STAGE is either a stage-spec file or a stage name (e.g. dc, \"Pumpkin Hill\", 25).");
+    println!("With -g, hints for a named stage are found in the game directory.");
+    println!();
+    println!("Pieces must be in hexadecimal format, major ID first.");
+    println!();
+    println!("Piece ID format (using 0x0A03 as an example):");
+    println!("0A03         Find a set that has piece 0x0A03 in that slot");
+    println!("0A03,0105    Accept any of the listed pieces in that slot");
+    println!("@enemy       Accept any piece of a category in that slot");
+    println!("!0A03        Accept any piece except the given one(s) (e.g. !@hidden)");
+    println!("G0A03        Mark that a given slot had piece 0x0A03 grabbed in the previous life");
+    println!("X            Don't care. Any piece may show up and it counts as a match");
+    println!();
+    println!("Categories: normal, hidden, underground, pathmove, tech, final, enemy");
+    println!("--none and --some take the same piece format and apply to all generated pieces,");
+    println!("e.g. --none @underground rejects sets with any piece underground.");
+    println!();
+    println!("Geometric options ignore grabbed slots. The --max-path route visits the pieces");
+    println!("in slot order, starting at --from if given.");
+    println!();
+    println!("With --near or --near-state, the closest matches before and after the given");
+    println!("point are printed instead, with the signed distance in RNG calls after the index.");
+    println!();
+    println!("Matches are always printed in index order, whatever the number of --jobs.");
+    println!("Indices covered by a set index (see 'sa2pg index') are read from it instead of");
+    println!("generated, unless a slot was grabbed.");
+    println!();
+    println!("The text format prints INDEX[,DISTANCE],P1,P2,P3, or tab-separated with the");
+    println!("first hint of each piece when hints are loaded. The other formats have the");
+    println!("fields rng_index, state, distance (with --near only), p1, p2, p3,");
+    println!("p1_x ... p3_z, and p1_h1 ... p3_h3 when hints are loaded.");
+    crate::print_field_notes();
+}
+
+/// Where matches go: the original text lines or a table format.
+enum Output<'a> {
+    Text(Option<&'a HintLookup>),
+    Table {
+        table: TableWriter<StdoutLock<'static>>,
+        columns: SetColumns,
+        lookup: Option<&'a HintLookup>,
+    },
+}
+
+impl<'a> Output<'a> {
+    fn new(format: Option<Format>, near: bool, lookup: Option<&'a HintLookup>) -> io::Result<Output<'a>> {
+        let format = match format {
+            Some(format) => format,
+            None => return Ok(Output::Text(lookup)),
+        };
+
+        let columns = SetColumns {
+            hex: true,
+            rng_index: true,
+            state: true,
+            positions: true,
+            hint_tiers: if lookup.is_some() { vec![1, 2, 3] } else { Vec::new() },
+        };
+        let mut headers = columns.headers();
+        if near {
+            headers.insert(2, "distance".to_string());
+        }
+
+        Ok(Output::Table {
+            table: TableWriter::new(io::stdout().lock(), format, headers)?,
+            columns,
+            lookup,
+        })
+    }
+
+    fn write(&mut self, index: u32, state: u32, distance: Option<i64>, pieces: &[Emerald; 3]) -> io::Result<()> {
+        match self {
+            Output::Text(lookup) => print_match(index, distance, pieces, *lookup),
+            Output::Table { table, columns, lookup } => {
+                let mut row = Vec::new();
+                columns.push_cells(&mut row, index as u64, state, pieces, *lookup);
+                if let Some(distance) = distance {
+                    row.insert(2, Cell::Int(distance));
+                }
+                table.write_row(&row)
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Text(_) => Ok(()),
+            Output::Table { table, .. } => table.finish(),
+        }
+    }
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let stage = crate::stage_args(matches)?;
+    let lookup = crate::hint_lookup(matches, stage.stage)?;
+    let constraints = parse_constraints(matches, &stage.spec)?;
+
+    let format = crate::table_format(matches, "text")?;
+
+    let near = crate::optional::<u32>(matches, "near")?;
+    let near_state = matches.opt_str("near-state").map(|s| crate::parse_state(&s)).transpose()?;
+    if near.is_some() || near_state.is_some() {
+        let count = crate::optional(matches, "n")?.unwrap_or(5);
+        let max_distance = crate::optional(matches, "max-distance")?.unwrap_or(1_000_000);
+        let mut output = Output::new(format, true, lookup.as_ref())?;
+        with_platform!(stage.platform, stage.sort, nearest(&stage.spec, near, near_state, count, max_distance, &constraints, &mut output))?;
+        return output.finish().map_err(CliError::from);
+    }
+
+    let begin = crate::optional::<u32>(matches, "b")?.unwrap_or(0) as u64;
+    let end = crate::optional::<u32>(matches, "e")?.map_or(FULL_PERIOD, |end| end as u64);
+    let jobs = crate::jobs(matches)?;
+    let index_path = match matches.opt_str("i") {
+        Some(path) => Some((PathBuf::from(path), true)),
+        None if !matches.opt_present("no-index") => Some((set_index::default_path(&stage.spec_path), false))
+            .filter(|(path, _)| path.is_file()),
+        None => None,
+    };
+
+    let mut output = Output::new(format, false, lookup.as_ref())?;
+    with_platform!(stage.platform, stage.sort, piece_sequence(&stage.spec, begin, end, jobs, index_path, &constraints, &mut output))?;
+    output.finish().map_err(CliError::from)
+}
+
+/// Reads the piece descriptors and set-wide options into constraints.
+pub fn parse_constraints(matches: &Matches, spec: &StageSpec) -> Result<SetConstraints, CliError> {
+    if matches.free.len() != 3 {
+        return Err(CliError::usage("expected 3 piece descriptors"));
+    }
+    let slot = |idx: usize| {
+        matches.free[idx].parse::<PieceConstraint>()
+            .map_err(|e| CliError::usage(format!("piece {}: {}", idx + 1, e)))
+    };
+
+    let mut constraints = SetConstraints::new(slot(0)?, slot(1)?, slot(2)?);
+    for none in matches.opt_strs("none") {
+        constraints.predicates.push(SetPredicate::NoneMatch(none.parse().map_err(|e| CliError::usage(format!("--none: {}", e)))?));
+    }
+    for some in matches.opt_strs("some") {
+        constraints.predicates.push(SetPredicate::SomeMatch(some.parse().map_err(|e| CliError::usage(format!("--some: {}", e)))?));
+    }
+
+    let vector = |name: &str, s: &str| constraint::parse_vector(s)
+        .map_err(|e| CliError::usage(format!("--{}: {}", name, e)));
+    if let Some(gap) = crate::optional(matches, "max-gap")? {
+        constraints.geometry.push(GeometryConstraint::MaxGap(gap));
+    }
+    if let Some(length) = crate::optional(matches, "max-path")? {
+        let start = matches.opt_str("from").map(|s| vector("from", &s)).transpose()?;
+        constraints.geometry.push(GeometryConstraint::MaxPathLength {
+            start,
+            length,
+        });
+    }
+    if let Some(radius) = crate::optional(matches, "within")? {
+        let center = matches.opt_str("center").ok_or_else(|| CliError::usage("--within needs --center"))?;
+        constraints.geometry.push(GeometryConstraint::WithinRadius {
+            center: vector("center", &center)?,
+            radius,
+        });
+    }
+
+    constraints.validate(spec).map_err(|e| CliError::usage(format!("invalid constraint: {}", e)))?;
+    Ok(constraints)
+}
+
+fn evaluate<P>(cache: &CandidateCache<P>, constraints: &SetConstraints, mut r: Rng) -> Option<[Emerald; 3]>
+    where P: Platform,
+{
+    let pieces = cache.gen_pieces(&mut r);
+    if constraints.matches_pieces::<P>(&pieces) {
+        Some(pieces)
+    }
+    else {
+        None
+    }
+}
+
+fn print_match(index: u32, distance: Option<i64>, pieces: &[Emerald; 3], lookup: Option<&HintLookup>) -> io::Result<()> {
+    let prefix = match distance {
+        Some(distance) => format!("{}{}{:+}", index, if lookup.is_some() { '\t' } else { ',' }, distance),
+        None => index.to_string(),
+    };
+
+    let mut stdout = io::stdout().lock();
+    if let Some(hints) = lookup {
+        let hint = |piece: &Emerald| if piece.id == GRABBED_ID {
+            "N/A".to_string()
+        }
+        else {
+            hints.lookup_piece(piece.id).h1.replace('\n', " ")
+        };
+        writeln!(stdout, "{}\t{:04X}\t{:04X}\t{:04X}\t{}\t{}\t{}", prefix, pieces[0].id, pieces[1].id, pieces[2].id, hint(&pieces[0]), hint(&pieces[1]), hint(&pieces[2]))
+    } else {
+        writeln!(stdout, "{},{:04X},{:04X},{:04X}", prefix, pieces[0].id, pieces[1].id, pieces[2].id)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn nearest<P>(spec: &StageSpec, near: Option<u32>, near_state: Option<u32>, count: usize, max_distance: u32, constraints: &SetConstraints, output: &mut Output) -> CliResult
+    where P: Platform,
+{
+    let cache = CandidateCache::<P>::with_grabbed(spec, constraints.grabbed_pieces(spec));
+    let center = match near_state {
+        Some(state) => Rng::index_of::<P::Consts>(state),
+        None => near.unwrap(),
+    };
+
+    // Indices before the boot seed don't exist, so stop at 0.
+    let mut before = Vec::new();
+    let mut r = Rng::at_index::<P::Consts>(center);
+    for distance in 1..=max_distance.min(center) {
+        if before.len() == count {
+            break;
+        }
+        r.step_back::<P::Consts>();
+        if let Some(pieces) = evaluate(&cache, constraints, r) {
+            before.push((center - distance, -(distance as i64), pieces));
+        }
+    }
+
+    let mut after = Vec::new();
+    let mut r = Rng::at_index::<P::Consts>(center);
+    for distance in 0..=max_distance {
+        if after.len() == count {
+            break;
+        }
+        if let Some(pieces) = evaluate(&cache, constraints, r) {
+            after.push((center.wrapping_add(distance), distance as i64, pieces));
+        }
+        r.gen_val::<P::Consts>();
+    }
+
+    for (index, distance, pieces) in before.iter().rev().chain(after.iter()) {
+        output.write(*index, Rng::at_index::<P::Consts>(*index).get_state(), Some(*distance), pieces)?;
+    }
+    Ok(())
+}
+
+fn piece_sequence<P>(spec: &StageSpec, begin: u64, end: u64, jobs: usize, index_path: Option<(PathBuf, bool)>, constraints: &SetConstraints, output: &mut Output) -> CliResult
+    where P: Platform,
+{
+    let grabbed = constraints.grabbed_pieces(spec);
+    let cache = CandidateCache::<P>::with_grabbed(spec, grabbed);
+
+    // Stop writing at the first error (e.g. a closed pipe), but let the scan finish.
+    let mut result = Ok(());
+    let mut emit = |index: u64, pieces: &[Emerald; 3]| {
+        if result.is_ok() {
+            result = output.write(index as u32, Rng::at_index::<P::Consts>(index as u32).get_state(), None, pieces);
+        }
+    };
+    let generate = |from, to, emit: &mut dyn FnMut(u64, &[Emerald; 3])| {
+        parallel::scan::<P::Consts, _, _, _>(from, to, jobs,
+            |r| evaluate(&cache, constraints, r),
+            |index, pieces| emit(index, &pieces));
+    };
+
+    // Grabbed slots change how many RNG calls a set takes, so the index
+    // doesn't apply to them.
+    let index = match index_path {
+        Some(_) if grabbed.iter().any(Option::is_some) => None,
+        Some((path, explicit)) => open_index::<P>(spec, &path, explicit)?,
+        None => None,
+    };
+
+    match index {
+        Some(mut index) => {
+            let covered_begin = begin.max(index.begin()).min(end);
+            let covered_end = end.min(index.end()).max(covered_begin);
+
+            generate(begin, covered_begin, &mut emit);
+            index.find(covered_begin, covered_end, |ids| {
+                constraints.matches_pieces::<P>(&ids_to_pieces(spec, ids))
+            }, |index, ids| {
+                emit(index, &ids_to_pieces(spec, ids))
+            }).map_err(CliError::context("reading set index"))?;
+            generate(covered_end, end, &mut emit);
+        }
+        None => generate(begin, end, &mut emit),
+    }
+    result.map_err(CliError::from)
+}
+
+/// Opens a set index. An index that was asked for must fit the stage; one
+/// that was only found next to the spec is skipped with a warning.
+fn open_index<P>(spec: &StageSpec, path: &Path, explicit: bool) -> Result<Option<SetIndex<BufReader<File>>>, CliError>
+    where P: Platform,
+{
+    let index = SetIndex::open(path)
+        .and_then(|index| index.check::<P>(spec).map(|_| index));
+
+    match index {
+        Ok(index) => Ok(Some(index)),
+        Err(e) if explicit => Err(CliError::failure(format!("loading set index {}: {}", path.display(), e))),
+        Err(e) => {
+            eprintln!("Ignoring set index {}: {}", path.display(), e);
+            Ok(None)
+        }
+    }
+}
+
+fn ids_to_pieces(spec: &StageSpec, ids: &[u16; 3]) -> [Emerald; 3] {
+    // The index passed its fingerprint check, so every ID is in the spec.
+    let piece = |id| spec.get_emerald_by_id(id).unwrap();
+    [piece(ids[0]), piece(ids[1]), piece(ids[2])]
+}
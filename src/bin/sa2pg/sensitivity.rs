@@ -0,0 +1,126 @@
+use std::io;
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::constraint::SetConstraints;
+use sa2_piece_gen::parallel;
+use sa2_piece_gen::sensitivity::{self, Analyzer, Sensitivity};
+use sa2_piece_gen::stage_spec::StageSpec;
+use sa2_piece_gen::table::{Cell, Format, TableWriter};
+use sa2_piece_gen::Platform;
+
+use crate::{CliError, CliResult};
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    crate::stage_options(&mut opts);
+    opts.optopt("b", "begin", "first RNG index to check (default 0)", "RNG_CALLS");
+    opts.optopt("e", "end", "RNG index to stop at (default 1000000)", "RNG_CALLS");
+    opts.optopt("t", "tolerance", &format!("relative key margin under which a pick is unreliable (default {})", sensitivity::DEFAULT_TOLERANCE), "MARGIN");
+    opts.optflag("a", "all", "list every index, not only unreliable ones");
+    crate::format_option(&mut opts, None);
+    crate::jobs_option(&mut opts);
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} -p PLATFORM -s STAGE [OPTIONS] [P1 P2 P3]", program);
+    println!("{}", opts.usage(&brief));
+    println!("Lists RNG indices whose set depends on float precision. The p2 and p3 picks");
+    println!("are positions in candidate lists sorted by distance and area; an index is");
+    println!("unreliable when the picked candidate's key is within the tolerance of another");
+    println!("candidate's, so a rounding difference would swap them, or when generating with");
+    println!("another math model the platform could use gives a different set: gekko or f32");
+    println!("on GC, f32, x87-53 or x87-64 on PC.");
+    println!();
+    println!("P1 P2 P3 mark grabbed slots the same way as 'sa2pg search': G0A03 for a piece");
+    println!("grabbed in the previous life, X otherwise.");
+    println!();
+    println!("Fields: rng_index, state, p1, p2, p3, p2_margin and p3_margin (relative gap");
+    println!("between the picked key and the closest other key, N/A for a grabbed slot),");
+    println!("reliable (yes or no) and other_sets (e.g. \"x87-64: 0303 0503 040C\" for each");
+    println!("math model that disagrees, separated by \"; \").");
+    crate::print_field_notes();
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let stage = crate::stage_args(matches)?;
+    let constraints = crate::table::grabbed_constraints(&matches.free, &stage.spec)?;
+    let begin = crate::optional::<u32>(matches, "b")?.unwrap_or(0) as u64;
+    let end = crate::optional::<u32>(matches, "e")?.unwrap_or(1_000_000) as u64;
+    if end <= begin {
+        return Err(CliError::usage("the range to check is empty"));
+    }
+    let tolerance = crate::optional::<f32>(matches, "t")?.unwrap_or(sensitivity::DEFAULT_TOLERANCE);
+    let format = crate::format(matches, Format::Csv)?;
+    let jobs = crate::jobs(matches)?;
+
+    let options = ReportOptions {
+        begin,
+        end,
+        tolerance,
+        all: matches.opt_present("a"),
+        jobs,
+        format,
+    };
+    with_platform!(stage.platform, stage.sort, write_report(&stage.spec, &constraints, &options))
+        .map_err(CliError::context("writing report"))
+}
+
+struct ReportOptions {
+    begin: u64,
+    end: u64,
+    tolerance: f32,
+    all: bool,
+    jobs: usize,
+    format: Format,
+}
+
+fn write_report<P>(spec: &StageSpec, constraints: &SetConstraints, options: &ReportOptions) -> io::Result<()>
+    where P: Platform,
+{
+    let headers = ["rng_index", "state", "p1", "p2", "p3", "p2_margin", "p3_margin", "reliable", "other_sets"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let stdout = io::stdout();
+    let mut table = TableWriter::new(stdout.lock(), options.format, headers)?;
+    let analyzer = Analyzer::<P>::new(spec, constraints.grabbed_pieces(spec));
+
+    let mut result = Ok(());
+    parallel::scan::<P::Consts, _, _, _>(options.begin, options.end, options.jobs, |r| {
+        let analysis = analyzer.analyze(r);
+        let reliable = analysis.is_reliable(options.tolerance);
+        if options.all || !reliable {
+            Some((r.get_state(), reliable, analysis))
+        }
+        else {
+            None
+        }
+    }, |index, (state, reliable, analysis)| {
+        if result.is_ok() {
+            result = table.write_row(&row(index, state, reliable, &analysis));
+        }
+    });
+    result?;
+
+    table.finish()
+}
+
+fn row(index: u64, state: u32, reliable: bool, analysis: &Sensitivity) -> Vec<Cell> {
+    let margin = |margin: Option<f32>| margin.map_or_else(|| Cell::Text("N/A".to_string()), Cell::Float);
+    let other_sets = analysis.other_sets.iter()
+        .map(|(model, ids)| format!("{}: {:04X} {:04X} {:04X}", model, ids[0], ids[1], ids[2]))
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    let mut row = vec![Cell::Int(index as i64), Cell::Text(format!("{:08X}", state))];
+    row.extend(analysis.pieces.iter().map(|piece| Cell::Text(format!("{:04X}", piece.id))));
+    row.extend_from_slice(&[
+        margin(analysis.p2_margin),
+        margin(analysis.p3_margin),
+        Cell::Text(if reliable { "yes" } else { "no" }.to_string()),
+        Cell::Text(other_sets),
+    ]);
+    row
+}
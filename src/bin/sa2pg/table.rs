@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use getopts::{Matches, Options};
+
+use sa2_piece_gen::candidate_cache::CandidateCache;
+use sa2_piece_gen::constraint::{PieceConstraint, SetConstraints};
+use sa2_piece_gen::hint_lookup::HintLookup;
+use sa2_piece_gen::parallel;
+use sa2_piece_gen::stage_spec::StageSpec;
+use sa2_piece_gen::table::{Cell, Format, SetColumns, TableWriter};
+use sa2_piece_gen::Platform;
+
+use crate::{CliError, CliResult};
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    crate::stage_options(&mut opts);
+    opts.optopt("", "pre-calls", "override the stage's RNG calls before generation", "RNG_CALLS");
+    opts.optopt("", "start", "first offset after the pre-calls (default 0)", "RNG_CALLS");
+    opts.optopt("n", "length", "number of rows (default 1024)", "ROWS");
+    opts.optopt("", "ids", "piece ID format: hex or dec (default hex)", "FORMAT");
+    opts.optflag("", "rng-index", "add a column with the absolute RNG index");
+    opts.optflag("", "state", "add a column with the RNG state");
+    opts.optflag("", "positions", "add the position of each piece");
+    opts.optopt("", "hints", "hint tiers to include (default 1)", "TIERS");
+    crate::hint_options(&mut opts);
+    crate::format_option(&mut opts, None);
+    opts.optopt("o", "output", "write to this file instead of standard output", "FILE");
+    crate::jobs_option(&mut opts);
+    opts
+}
+
+pub fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} -p PLATFORM -s STAGE [OPTIONS] [P1 P2 P3]", program);
+    println!("{}", opts.usage(&brief));
+    println!("Prints the set generated at each RNG call after the stage's pre-calls, one row");
+    println!("per call. Rows are numbered by that offset; --rng-index adds the absolute index.");
+    println!();
+    println!("P1 P2 P3 mark grabbed slots the same way as 'sa2pg search': G0A03 for a piece");
+    println!("grabbed in the previous life, X otherwise.");
+    println!();
+    println!("Hint tiers are a comma-separated list of 1, 2 and 3, or \"all\". Hints are only");
+    println!("included with -l or -g.");
+    println!();
+    println!("Fields: offset, then rng_index and state if asked for, p1, p2, p3, then");
+    println!("p1_x ... p3_z with --positions and p1_h1 ... p3_h3 for the hint tiers.");
+    crate::print_field_notes();
+}
+
+pub fn run(matches: &Matches) -> CliResult {
+    let mut stage = crate::stage_args(matches)?;
+    let lookup = crate::hint_lookup(matches, stage.stage)?;
+    if let Some(pre_calls) = crate::optional(matches, "pre-calls")? {
+        stage.spec.pre_calls = pre_calls;
+    }
+
+    let start = crate::optional(matches, "start")?.unwrap_or(0);
+    let length = crate::optional(matches, "n")?.unwrap_or(1024);
+    let format = crate::format(matches, Format::Csv)?;
+    let jobs = crate::jobs(matches)?;
+
+    let hex = match matches.opt_str("ids").as_deref() {
+        None | Some("hex") => true,
+        Some("dec") => false,
+        Some(other) => return Err(CliError::usage(format!("unknown piece ID format '{}' (expected hex or dec)", other))),
+    };
+    let hint_tiers = match matches.opt_str("hints").as_deref() {
+        _ if lookup.is_none() => Vec::new(),
+        None => vec![1],
+        Some("all") => vec![1, 2, 3],
+        Some(tiers) => tiers.split(',')
+            .map(|tier| match tier.trim().parse() {
+                Ok(tier @ 1..=3) => Ok(tier),
+                _ => Err(CliError::usage(format!("invalid hint tier '{}'", tier))),
+            })
+            .collect::<Result<_, _>>()?,
+    };
+    let columns = SetColumns {
+        hex,
+        rng_index: matches.opt_present("rng-index"),
+        state: matches.opt_present("state"),
+        positions: matches.opt_present("positions"),
+        hint_tiers,
+    };
+
+    let constraints = grabbed_constraints(&matches.free, &stage.spec)?;
+
+    let output: Box<dyn Write> = match matches.opt_str("o") {
+        Some(path) => Box::new(File::create(&path).map_err(CliError::context(&format!("creating {}", path)))?),
+        None => Box::new(io::stdout()),
+    };
+
+    with_platform!(stage.platform, stage.sort, gen_table(&stage.spec, start, length, jobs, &constraints, &columns, lookup.as_ref(), format, output))
+        .map_err(CliError::context("writing table"))
+}
+
+/// Reads optional P1 P2 P3 descriptors that may only mark grabbed slots.
+pub fn grabbed_constraints(free: &[String], spec: &StageSpec) -> Result<SetConstraints, CliError> {
+    let constraints = match free.len() {
+        0 => SetConstraints::new(PieceConstraint::DontCare, PieceConstraint::DontCare, PieceConstraint::DontCare),
+        3 => {
+            let slot = |idx: usize| {
+                let constraint = free[idx].parse::<PieceConstraint>()
+                    .map_err(|e| CliError::usage(format!("piece {}: {}", idx + 1, e)))?;
+                match constraint {
+                    PieceConstraint::GrabbedId(_) | PieceConstraint::DontCare => Ok(constraint),
+                    PieceConstraint::Want(_) => Err(CliError::usage(format!("piece {}: only G (grabbed) or X are allowed", idx + 1))),
+                }
+            };
+            SetConstraints::new(slot(0)?, slot(1)?, slot(2)?)
+        }
+        _ => return Err(CliError::usage("expected 0 or 3 piece descriptors")),
+    };
+
+    constraints.validate(spec).map_err(|e| CliError::usage(format!("invalid constraint: {}", e)))?;
+    Ok(constraints)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn gen_table<P>(spec: &StageSpec, start: u32, length: u32, jobs: usize, constraints: &SetConstraints, columns: &SetColumns, lookup: Option<&HintLookup>, format: Format, output: Box<dyn Write>) -> io::Result<()>
+    where P: Platform,
+{
+    let mut headers = vec!["offset".to_string()];
+    headers.extend(columns.headers());
+    let mut table = TableWriter::new(BufWriter::new(output), format, headers)?;
+    let cache = CandidateCache::<P>::with_grabbed(spec, constraints.grabbed_pieces(spec));
+    let begin = spec.pre_calls as u64 + start as u64;
+
+    let mut result = Ok(());
+    parallel::scan::<P::Consts, _, _, _>(begin, begin + length as u64, jobs, |mut r| Some((r.get_state(), cache.gen_pieces(&mut r))), |index, (state, pieces)| {
+        if result.is_ok() {
+            let mut row = vec![Cell::Int((index - spec.pre_calls as u64) as i64)];
+            columns.push_cells(&mut row, index, state, &pieces, lookup);
+            result = table.write_row(&row);
+        }
+    });
+    result?;
+
+    table.finish()
+}
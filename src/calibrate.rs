@@ -0,0 +1,230 @@
+//! Finds the `pre_calls` value that best explains sets seen on fresh stage
+//! loads.
+//!
+//! Every observation was generated at `pre_calls` plus a small offset, as
+//! loads are not always frame-perfect. A candidate value explains an
+//! observation when some index in `pre_calls..=pre_calls + max_offset`
+//! generates a matching set. Each index in the searched range is generated
+//! once, and the number of observations every candidate value explains is
+//! then counted from the matching indices.
+
+use std::ops::Range;
+
+use crate::candidate_cache::CandidateCache;
+use crate::constraint::SetConstraints;
+use crate::parallel;
+use crate::stage_spec::StageSpec;
+use crate::Platform;
+
+/// `false_matches` above which a result isn't trusted enough to save.
+pub const MAX_FALSE_MATCHES: f64 = 0.01;
+
+/// The best `pre_calls` value for a list of observations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Calibration {
+    pub pre_calls: u32,
+    /// Offset after `pre_calls` of the first index matching each observation,
+    /// or `None` for observations it does not explain.
+    pub offsets: Vec<Option<u32>>,
+    pub explained: usize,
+    /// The best value more than `max_offset` away, and how many observations
+    /// it explains.
+    pub runner_up: Option<(u32, usize)>,
+    /// Chance that a value picked at random explains at least as many
+    /// observations, from how often each observed set comes up in the range.
+    pub p_value: f64,
+    /// Number of non-overlapping offset windows in the searched range.
+    pub trials: f64,
+}
+
+impl Calibration {
+    /// Expected number of values in the searched range that would explain as
+    /// many observations by coincidence. Well below 1 means the result can be
+    /// trusted.
+    pub fn false_matches(&self) -> f64 {
+        self.p_value * self.trials
+    }
+
+    /// Whether `false_matches` is at most `MAX_FALSE_MATCHES`.
+    pub fn is_confident(&self) -> bool {
+        self.false_matches() <= MAX_FALSE_MATCHES
+    }
+}
+
+/// Searches `range` for the `pre_calls` value explaining the most
+/// observations. Ties go to the earliest run of equally good values, and
+/// within it to the last one, which has the smallest offsets. Returns `None`
+/// for an empty range.
+pub fn calibrate<P>(spec: &StageSpec, observations: &[SetConstraints], range: Range<u32>, max_offset: u32, jobs: usize) -> Option<Calibration>
+    where P: Platform,
+{
+    if range.is_empty() {
+        return None;
+    }
+
+    let cache = CandidateCache::<P>::new(spec);
+    let end = range.end as u64 + max_offset as u64;
+    let mut positions = vec![Vec::new(); observations.len()];
+    parallel::scan::<P::Consts, _, _, _>(range.start as u64, end, jobs, |mut r| {
+        let ids = cache.gen_pieces(&mut r).map(|piece| piece.id);
+        let matching: Vec<usize> = observations.iter()
+            .enumerate()
+            .filter(|(_, observation)| observation.matches(ids))
+            .map(|(idx, _)| idx)
+            .collect();
+        if matching.is_empty() { None } else { Some(matching) }
+    }, |index, matching| {
+        for idx in matching {
+            positions[idx].push(index as u32);
+        }
+    });
+
+    let scores = scores(&positions, range.clone(), max_offset);
+    let best_score = *scores.iter().max()?;
+    let first = scores.iter().position(|&score| score == best_score)?;
+    let run = scores[first..].iter().take_while(|&&score| score == best_score).count();
+    let pre_calls = range.start + (first + run - 1) as u32;
+
+    let offsets: Vec<Option<u32>> = positions.iter()
+        .map(|matches| {
+            matches.iter()
+                .find(|&&index| index >= pre_calls && index - pre_calls <= max_offset)
+                .map(|&index| index - pre_calls)
+        })
+        .collect();
+
+    let runner_up = scores.iter()
+        .enumerate()
+        .map(|(idx, &score)| (range.start + idx as u32, score))
+        .filter(|&(value, _)| value.abs_diff(pre_calls) > max_offset)
+        .fold(None, |best: Option<(u32, usize)>, (value, score)| match best {
+            Some((_, best_score)) if best_score >= score => best,
+            _ => Some((value, score)),
+        });
+
+    // How likely one window of offsets is to contain each observed set.
+    let length = (end - range.start as u64) as f64;
+    let window = max_offset as f64 + 1.0;
+    let chances: Vec<f64> = positions.iter()
+        .map(|matches| 1.0 - (1.0 - matches.len() as f64 / length).powf(window))
+        .collect();
+
+    Some(Calibration {
+        pre_calls,
+        offsets,
+        explained: best_score,
+        runner_up,
+        p_value: tail_probability(&chances, best_score),
+        trials: (range.len() as f64 / window).max(1.0),
+    })
+}
+
+/// Number of observations each value in `range` explains, given the indices
+/// matching each observation in ascending order.
+fn scores(positions: &[Vec<u32>], range: Range<u32>, max_offset: u32) -> Vec<usize> {
+    // Each match explains its observation for the values up to max_offset
+    // before it. Overlapping stretches are merged so every observation counts
+    // once per value, then added up through a difference array.
+    let mut diff = vec![0isize; range.len() + 1];
+    for matches in positions {
+        let mut stretch: Option<(u32, u32)> = None;
+        for &index in matches {
+            let start = index.saturating_sub(max_offset).max(range.start);
+            let stop = index.min(range.end - 1);
+            if start > stop {
+                continue;
+            }
+            stretch = match stretch {
+                Some((first, last)) if start <= last + 1 => Some((first, stop)),
+                Some((first, last)) => {
+                    diff[(first - range.start) as usize] += 1;
+                    diff[(last - range.start) as usize + 1] -= 1;
+                    Some((start, stop))
+                }
+                None => Some((start, stop)),
+            };
+        }
+        if let Some((first, last)) = stretch {
+            diff[(first - range.start) as usize] += 1;
+            diff[(last - range.start) as usize + 1] -= 1;
+        }
+    }
+
+    let mut score = 0;
+    diff[..range.len()].iter()
+        .map(|&delta| {
+            score += delta;
+            score as usize
+        })
+        .collect()
+}
+
+/// Chance that at least `k` of the independent events with probabilities
+/// `chances` happen.
+fn tail_probability(chances: &[f64], k: usize) -> f64 {
+    // dist[n] is the chance that exactly n of the events seen so far happen.
+    let mut dist = vec![1.0];
+    for &p in chances {
+        let mut next = vec![0.0; dist.len() + 1];
+        for (n, &q) in dist.iter().enumerate() {
+            next[n] += q * (1.0 - p);
+            next[n + 1] += q * p;
+        }
+        dist = next;
+    }
+    dist.iter().skip(k).sum::<f64>().min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::constraint::{PieceConstraint, PieceMatch};
+    use crate::emerald_manager::gen_set;
+    use crate::rng::{GcRng, Rng};
+    use crate::stage_spec::load_bundled;
+    use crate::Gc;
+
+    #[test]
+    fn test_scores() {
+        let positions = vec![vec![3, 4, 9], vec![5]];
+        assert_eq!(scores(&positions, 0..10, 0), [0, 0, 0, 1, 1, 1, 0, 0, 0, 1]);
+        assert_eq!(scores(&positions, 0..10, 2), [0, 1, 1, 2, 2, 1, 0, 1, 1, 1]);
+        assert_eq!(scores(&positions, 4..8, 2), [2, 1, 0, 1]);
+
+        assert_eq!(tail_probability(&[0.5, 0.5], 0), 1.0);
+        assert_eq!(tail_probability(&[0.5, 0.5], 1), 0.75);
+        assert_eq!(tail_probability(&[0.5, 0.5], 2), 0.25);
+        assert_eq!(tail_probability(&[0.5, 0.5], 3), 0.0);
+    }
+
+    #[test]
+    fn test_calibrate() {
+        let spec = load_bundled("GC/dc_spec_gc.txt");
+
+        // Sets from a few loads that came in up to 3 calls late.
+        let observations: Vec<SetConstraints> = [0, 2, 3, 1, 0, 2]
+            .iter()
+            .map(|&offset| {
+                let r = Rng::at_index::<GcRng>(spec.pre_calls + offset);
+                let pieces = gen_set::<Gc>(&spec, r, [None, None, None]).pieces;
+                let slot = |idx: usize| PieceConstraint::Want(PieceMatch::Id(pieces[idx].id));
+                SetConstraints::new(slot(0), slot(1), slot(2))
+            })
+            .collect();
+
+        let calibration = calibrate::<Gc>(&spec, &observations, 0..20000, 3, 1).unwrap();
+        assert_eq!(calibration.pre_calls, spec.pre_calls);
+        assert_eq!(calibration.explained, 6);
+        assert_eq!(calibration.offsets[..3], [Some(0), Some(2), Some(3)]);
+        assert!(calibration.runner_up.is_some_and(|(_, explained)| explained < 6));
+        assert!(calibration.false_matches() < 1e-6);
+        assert!(calibration.is_confident());
+
+        // A single common set could be explained by chance.
+        let calibration = calibrate::<Gc>(&spec, &observations[..1], 0..20000, 3, 1).unwrap();
+        assert!(!calibration.is_confident());
+
+        assert_eq!(calibrate::<Gc>(&spec, &observations, 5..5, 3, 1), None);
+    }
+}
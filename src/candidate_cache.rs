@@ -0,0 +1,206 @@
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+
+use crate::emerald_manager::{far_half_index, p1_index, p2_candidates, sort_p2_candidates, sort_p3_candidates};
+use crate::rng::Rng;
+use crate::stage_spec::{Emerald, StageSpec};
+use crate::Platform;
+
+/// Precomputed candidate orderings for one stage and platform.
+///
+/// The p2 order only depends on which p1 was picked, and the p3 order only on
+/// the (p1, p2) pair, so both are sorted once up front. Generating a set is
+/// then three RNG calls and three table lookups, with the same result as
+/// `EmeraldManager::gen_pieces`.
+#[derive(Clone, Debug)]
+pub struct CandidateCache<P> {
+    p1_choices: Vec<P1Choice>,
+    grabbed: [bool; 3],
+    p: PhantomData<fn() -> P>,
+}
+
+#[derive(Clone, Debug)]
+struct P1Choice {
+    piece: Emerald,
+    p2_choices: Vec<P2Choice>,
+}
+
+#[derive(Clone, Debug)]
+struct P2Choice {
+    piece: Emerald,
+    p3_order: Vec<Emerald>,
+}
+
+impl<P> CandidateCache<P>
+    where P: Platform,
+{
+    pub fn new(spec: &StageSpec) -> CandidateCache<P> {
+        Self::with_grabbed(spec, [None, None, None])
+    }
+
+    /// Builds the cache for slots whose pieces were grabbed in a previous
+    /// life. Grabbed pieces keep their position but should carry
+    /// `GRABBED_ID`, like the ones set up by `SetConstraints::apply_grabbed`.
+    pub fn with_grabbed(spec: &StageSpec, grabbed: [Option<Emerald>; 3]) -> CandidateCache<P> {
+        // Mirror gen_pieces: an enemy p1 is swap_removed from the enemy list
+        // before the p2 candidates are gathered.
+        let p1_choices: Vec<(Emerald, Option<usize>)> = match grabbed[0] {
+            Some(piece) => vec![(piece, None)],
+            None => {
+                let slot1 = spec.slot1_pieces.iter()
+                    .map(|&piece| (piece, None));
+                let enemies = spec.enemy_pieces.iter()
+                    .enumerate()
+                    .map(|(idx, &piece)| (piece, Some(idx)));
+                slot1.chain(enemies).collect()
+            }
+        };
+
+        let p1_choices = p1_choices.into_iter()
+            .map(|(p1, removed_enemy)| {
+                let p2_order = match grabbed[1] {
+                    Some(piece) => vec![piece],
+                    None => {
+                        let mut potential_p2 = p2_candidates(&spec.slot2_pieces, &spec.enemy_pieces, removed_enemy);
+                        sort_p2_candidates::<P>(&mut potential_p2, p1.position);
+                        potential_p2
+                    }
+                };
+
+                let p2_choices = p2_order.into_iter()
+                    .map(|p2| {
+                        let p3_order = match grabbed[2] {
+                            Some(piece) => vec![piece],
+                            None => {
+                                let mut potential_p3 = spec.slot3_pieces.clone();
+                                sort_p3_candidates::<P>(&mut potential_p3, p1.position, p2.position);
+                                potential_p3
+                            }
+                        };
+                        P2Choice {
+                            piece: p2,
+                            p3_order,
+                        }
+                    })
+                    .collect();
+
+                P1Choice {
+                    piece: p1,
+                    p2_choices,
+                }
+            })
+            .collect();
+
+        CandidateCache {
+            p1_choices,
+            grabbed: [grabbed[0].is_some(), grabbed[1].is_some(), grabbed[2].is_some()],
+            p: PhantomData,
+        }
+    }
+
+    /// Generates the set for the RNG, advancing it past the calls generation makes.
+    pub fn gen_pieces(&self, r: &mut Rng) -> [Emerald; 3] {
+        let p1 = if self.grabbed[0] {
+            &self.p1_choices[0]
+        }
+        else {
+            &self.p1_choices[p1_index(r.gen_val::<P::Consts>(), self.p1_choices.len())]
+        };
+
+        let p2 = if self.grabbed[1] {
+            &p1.p2_choices[0]
+        }
+        else {
+            &p1.p2_choices[far_half_index(r.gen_val::<P::Consts>(), p1.p2_choices.len())]
+        };
+
+        let p3 = if self.grabbed[2] {
+            p2.p3_order[0]
+        }
+        else {
+            p2.p3_order[far_half_index(r.gen_val::<P::Consts>(), p2.p3_order.len())]
+        };
+
+        [p1.piece, p2.piece, p3]
+    }
+
+    /// Every set `gen_pieces` can produce, in cache order. Sets reachable
+    /// through more than one choice path are listed once per path.
+    pub fn reachable_sets(&self) -> Vec<[Emerald; 3]> {
+        let mut sets = Vec::new();
+
+        let p1_range = if self.grabbed[0] { 0..=0 } else { 0..=p1_index(0x7FFF, self.p1_choices.len()) };
+        for p1 in &self.p1_choices[p1_range] {
+            let p2_range = if self.grabbed[1] { 0..=0 } else { far_half_range(p1.p2_choices.len()) };
+            for p2 in &p1.p2_choices[p2_range] {
+                let p3_range = if self.grabbed[2] { 0..=0 } else { far_half_range(p2.p3_order.len()) };
+                for &p3 in &p2.p3_order[p3_range] {
+                    sets.push([p1.piece, p2.piece, p3]);
+                }
+            }
+        }
+
+        sets
+    }
+}
+
+/// Indices `far_half_index` can return for `num` candidates. It is monotonic
+/// in the RNG value and steps by less than one, so the range has no gaps.
+fn far_half_range(num: usize) -> RangeInclusive<usize> {
+    far_half_index(0x7FFF, num)..=far_half_index(0, num)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::constraint::GRABBED_ID;
+    use crate::emerald_manager::EmeraldManager;
+    use crate::stage_spec::load_bundled;
+    use crate::{Gc, Pc};
+
+    fn check_against_manager<P>(spec: &StageSpec, grabbed: [Option<Emerald>; 3])
+        where P: Platform,
+    {
+        let cache = CandidateCache::<P>::with_grabbed(spec, grabbed);
+        let mut r = Rng::at_index::<P::Consts>(spec.pre_calls);
+
+        for _ in 0..2000 {
+            let mut em = EmeraldManager::from_spec::<P>(spec.clone());
+            em.p1 = grabbed[0].unwrap_or(em.p1);
+            em.p2 = grabbed[1].unwrap_or(em.p2);
+            em.p3 = grabbed[2].unwrap_or(em.p3);
+            em.r = r;
+            em.gen_pieces::<P>();
+
+            let mut cached_r = r;
+            let pieces = cache.gen_pieces(&mut cached_r);
+            assert_eq!([pieces[0].id, pieces[1].id, pieces[2].id], [em.p1.id, em.p2.id, em.p3.id]);
+            assert_eq!(cached_r.get_state(), em.r.get_state());
+
+            r.gen_val::<P::Consts>();
+        }
+    }
+
+    #[test]
+    fn test_matches_emerald_manager() {
+        for stage in ["dc", "ph", "sh", "wc"].iter() {
+            check_against_manager::<Pc>(&load_bundled(&format!("PC/{}_spec_pc.txt", stage)), [None, None, None]);
+            check_against_manager::<Gc>(&load_bundled(&format!("GC/{}_spec_gc.txt", stage)), [None, None, None]);
+        }
+    }
+
+    #[test]
+    fn test_matches_emerald_manager_grabbed() {
+        let spec = load_bundled("PC/dc_spec_pc.txt");
+        let grab = |id| {
+            let mut piece = spec.get_emerald_by_id(id).unwrap();
+            piece.id = GRABBED_ID;
+            Some(piece)
+        };
+
+        check_against_manager::<Pc>(&spec, [grab(0x0307), None, None]);
+        check_against_manager::<Pc>(&spec, [None, grab(0x0202), None]);
+        check_against_manager::<Pc>(&spec, [grab(0x0A02), None, grab(0x0409)]);
+    }
+}
@@ -0,0 +1,412 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::emerald_manager::EmeraldManager;
+use crate::stage_spec::{Emerald, StageSpec};
+use crate::vector::Vector;
+use crate::Platform;
+
+/// ID given to a piece slot whose piece was grabbed in a previous life.
+pub const GRABBED_ID: u16 = 0xFE00;
+
+#[derive(Debug)]
+pub struct ParseConstraintError(pub(crate) String);
+
+impl fmt::Display for ParseConstraintError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for ParseConstraintError {}
+
+/// Piece groups, following the major ID layout of the hint files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    Normal,
+    Hidden,
+    Underground,
+    PathMove,
+    Tech,
+    Final,
+    Enemy,
+}
+
+impl Category {
+    pub fn from_name(s: &str) -> Option<Category> {
+        match s.to_lowercase().as_str() {
+            "normal" => Some(Category::Normal),
+            "hidden" => Some(Category::Hidden),
+            "underground" | "undergnd" => Some(Category::Underground),
+            "pathmove" | "path" => Some(Category::PathMove),
+            "tech" => Some(Category::Tech),
+            "final" => Some(Category::Final),
+            "enemy" | "inenemy" => Some(Category::Enemy),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Normal => "normal",
+            Category::Hidden => "hidden",
+            Category::Underground => "underground",
+            Category::PathMove => "pathmove",
+            Category::Tech => "tech",
+            Category::Final => "final",
+            Category::Enemy => "enemy",
+        }
+    }
+
+    pub fn of(id: u16) -> Option<Category> {
+        match id >> 8 {
+            0x00 | 0x01 => Some(Category::Normal),
+            0x02 | 0x03 => Some(Category::Hidden),
+            0x04..=0x06 => Some(Category::Underground),
+            0x07 => Some(Category::PathMove),
+            0x08 => Some(Category::Tech),
+            0x09 => Some(Category::Final),
+            0x0A => Some(Category::Enemy),
+            _ => None,
+        }
+    }
+}
+
+/// Condition on a single generated piece.
+#[derive(Clone, Debug)]
+pub enum PieceMatch {
+    Id(u16),
+    Category(Category),
+    AnyOf(Vec<PieceMatch>),
+    Not(Box<PieceMatch>),
+}
+
+impl FromStr for PieceMatch {
+    type Err = ParseConstraintError;
+
+    /// Parses `0105`, `@hidden`, comma-separated lists of those
+    /// (`0105,0A03,@enemy`) and negations of any of them (`!0105`).
+    fn from_str(s: &str) -> Result<PieceMatch, ParseConstraintError> {
+        if let Some(rest) = s.strip_prefix('!') {
+            return Ok(PieceMatch::Not(Box::new(rest.parse()?)));
+        }
+
+        let mut alternatives = s.split(',')
+            .map(|alt| {
+                if let Some(name) = alt.strip_prefix('@') {
+                    Category::from_name(name)
+                        .map(PieceMatch::Category)
+                        .ok_or_else(|| ParseConstraintError(format!("unknown piece category \"{}\"", name)))
+                }
+                else {
+                    u16::from_str_radix(alt, 16)
+                        .map(PieceMatch::Id)
+                        .map_err(|e| ParseConstraintError(format!("bad piece ID \"{}\": {}", alt, e)))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if alternatives.len() == 1 {
+            Ok(alternatives.remove(0))
+        }
+        else {
+            Ok(PieceMatch::AnyOf(alternatives))
+        }
+    }
+}
+
+/// Writes the form `from_str` parses.
+impl fmt::Display for PieceMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PieceMatch::Id(id) => write!(f, "{:04X}", id),
+            PieceMatch::Category(category) => write!(f, "@{}", category.name()),
+            PieceMatch::AnyOf(ref alternatives) => {
+                let alternatives: Vec<String> = alternatives.iter().map(|alt| alt.to_string()).collect();
+                f.write_str(&alternatives.join(","))
+            }
+            PieceMatch::Not(ref inner) => write!(f, "!{}", inner),
+        }
+    }
+}
+
+impl PieceMatch {
+    pub fn matches(&self, id: u16) -> bool {
+        match *self {
+            PieceMatch::Id(want) => id == want,
+            PieceMatch::Category(category) => Category::of(id) == Some(category),
+            PieceMatch::AnyOf(ref alternatives) => alternatives.iter().any(|alt| alt.matches(id)),
+            PieceMatch::Not(ref inner) => !inner.matches(id),
+        }
+    }
+
+    /// Checks that every piece ID named in the match exists in the stage.
+    pub fn validate(&self, spec: &StageSpec) -> Result<(), ParseConstraintError> {
+        match *self {
+            PieceMatch::Id(id) => {
+                spec.get_emerald_by_id(id)
+                    .map(|_| ())
+                    .ok_or_else(|| ParseConstraintError(format!("piece {:04X} is not present in stage", id)))
+            }
+            PieceMatch::Category(_) => Ok(()),
+            PieceMatch::AnyOf(ref alternatives) => alternatives.iter().try_for_each(|alt| alt.validate(spec)),
+            PieceMatch::Not(ref inner) => inner.validate(spec),
+        }
+    }
+}
+
+/// Constraint on one piece slot.
+#[derive(Clone, Debug)]
+pub enum PieceConstraint {
+    Want(PieceMatch),
+    GrabbedId(u16),
+    DontCare,
+}
+
+impl FromStr for PieceConstraint {
+    type Err = ParseConstraintError;
+
+    fn from_str(s: &str) -> Result<PieceConstraint, ParseConstraintError> {
+        if let Some(id) = s.strip_prefix('G') {
+            u16::from_str_radix(id, 16)
+                .map(PieceConstraint::GrabbedId)
+                .map_err(|e| ParseConstraintError(format!("bad grabbed piece ID \"{}\": {}", id, e)))
+        }
+        else if s == "X" {
+            Ok(PieceConstraint::DontCare)
+        }
+        else {
+            s.parse().map(PieceConstraint::Want)
+        }
+    }
+}
+
+/// Condition over all three generated pieces. Grabbed slots are ignored.
+#[derive(Clone, Debug)]
+pub enum SetPredicate {
+    /// No generated piece may match (e.g. "no piece underground").
+    NoneMatch(PieceMatch),
+    /// At least one generated piece must match.
+    SomeMatch(PieceMatch),
+}
+
+impl SetPredicate {
+    pub fn matches(&self, ids: [u16; 3]) -> bool {
+        let mut generated = ids.iter().filter(|&&id| id != GRABBED_ID);
+        match *self {
+            SetPredicate::NoneMatch(ref m) => !generated.any(|&id| m.matches(id)),
+            SetPredicate::SomeMatch(ref m) => generated.any(|&id| m.matches(id)),
+        }
+    }
+}
+
+/// Condition on where the generated pieces are. Grabbed slots are left out of
+/// the route, and distances use the platform's math like generation does.
+#[derive(Clone, Copy, Debug)]
+pub enum GeometryConstraint {
+    /// Maximum distance between consecutive generated pieces (p1 to p2, p2 to p3).
+    MaxGap(f32),
+    /// Maximum length of the route start -> p1 -> p2 -> p3. Without a start
+    /// point the route begins at the first generated piece.
+    MaxPathLength {
+        start: Option<Vector>,
+        length: f32,
+    },
+    /// Every generated piece lies within `radius` of `center`.
+    WithinRadius {
+        center: Vector,
+        radius: f32,
+    },
+}
+
+impl GeometryConstraint {
+    pub fn matches<P>(&self, pieces: &[Emerald; 3]) -> bool
+        where P: Platform,
+    {
+        let route: Vec<Vector> = pieces.iter()
+            .filter(|piece| piece.id != GRABBED_ID)
+            .map(|piece| piece.position)
+            .collect();
+
+        match *self {
+            GeometryConstraint::MaxGap(gap) => {
+                route.windows(2)
+                    .all(|pair| pair[0].distance::<P::Math>(pair[1]) <= gap)
+            }
+            GeometryConstraint::MaxPathLength { start, length } => {
+                let total: f32 = start.iter()
+                    .chain(route.iter())
+                    .zip(start.iter().chain(route.iter()).skip(1))
+                    .map(|(from, to)| from.distance::<P::Math>(*to))
+                    .sum();
+                total <= length
+            }
+            GeometryConstraint::WithinRadius { center, radius } => {
+                route.iter()
+                    .all(|position| position.distance::<P::Math>(center) <= radius)
+            }
+        }
+    }
+}
+
+/// Parses a point given as `x,y,z`.
+pub fn parse_vector(s: &str) -> Result<Vector, ParseConstraintError> {
+    let coords = s.split(',')
+        .map(|c| c.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ParseConstraintError(format!("bad point \"{}\": {}", s, e)))?;
+
+    if coords.len() != 3 {
+        return Err(ParseConstraintError(format!("bad point \"{}\": expected x,y,z", s)));
+    }
+    Ok(Vector::new(coords[0], coords[1], coords[2]))
+}
+
+#[derive(Clone, Debug)]
+pub struct SetConstraints {
+    pub slots: [PieceConstraint; 3],
+    pub predicates: Vec<SetPredicate>,
+    pub geometry: Vec<GeometryConstraint>,
+}
+
+impl SetConstraints {
+    pub fn new(p1: PieceConstraint, p2: PieceConstraint, p3: PieceConstraint) -> SetConstraints {
+        SetConstraints {
+            slots: [p1, p2, p3],
+            predicates: Vec::new(),
+            geometry: Vec::new(),
+        }
+    }
+
+    pub fn validate(&self, spec: &StageSpec) -> Result<(), ParseConstraintError> {
+        for (slot, constraint) in self.slots.iter().enumerate() {
+            let result = match *constraint {
+                PieceConstraint::Want(ref m) => m.validate(spec),
+                PieceConstraint::GrabbedId(id) => PieceMatch::Id(id).validate(spec),
+                PieceConstraint::DontCare => Ok(()),
+            };
+            result.map_err(|e| ParseConstraintError(format!("p{}: {}", slot + 1, e)))?;
+        }
+        for predicate in self.predicates.iter() {
+            match *predicate {
+                SetPredicate::NoneMatch(ref m) | SetPredicate::SomeMatch(ref m) => m.validate(spec)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Grabbed piece for each slot, carrying `GRABBED_ID` but keeping its position.
+    pub fn grabbed_pieces(&self, spec: &StageSpec) -> [Option<Emerald>; 3] {
+        let mut grabbed = [None; 3];
+        for (constraint, slot) in self.slots.iter().zip(grabbed.iter_mut()) {
+            if let PieceConstraint::GrabbedId(id) = *constraint {
+                let mut piece = spec.get_emerald_by_id(id).expect("Grabbed piece not present in stage");
+                piece.id = GRABBED_ID;
+                *slot = Some(piece);
+            }
+        }
+        grabbed
+    }
+
+    /// Marks grabbed slots on a fresh manager so generation skips them.
+    pub fn apply_grabbed(&self, spec: &StageSpec, em: &mut EmeraldManager) {
+        let targets = [&mut em.p1, &mut em.p2, &mut em.p3];
+        for (grabbed, target) in self.grabbed_pieces(spec).iter().zip(targets) {
+            if let Some(piece) = *grabbed {
+                *target = piece;
+            }
+        }
+    }
+
+    pub fn matches(&self, ids: [u16; 3]) -> bool {
+        let slots_match = self.slots.iter()
+            .zip(ids.iter())
+            .all(|(constraint, &id)| match *constraint {
+                PieceConstraint::Want(ref m) => m.matches(id),
+                _ => true,
+            });
+
+        slots_match && self.predicates.iter().all(|predicate| predicate.matches(ids))
+    }
+
+    /// Like `matches`, also checking the geometric constraints.
+    pub fn matches_pieces<P>(&self, pieces: &[Emerald; 3]) -> bool
+        where P: Platform,
+    {
+        self.matches([pieces[0].id, pieces[1].id, pieces[2].id]) &&
+            self.geometry.iter().all(|geometry| geometry.matches::<P>(pieces))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Pc;
+
+    #[test]
+    fn test_piece_match() {
+        let m = "0105,0A03,@hidden".parse::<PieceMatch>().unwrap();
+        assert!(m.matches(0x0105));
+        assert!(m.matches(0x0A03));
+        assert!(m.matches(0x0302));
+        assert!(!m.matches(0x0106));
+        assert_eq!(m.to_string(), "0105,0A03,@hidden");
+
+        let m = "!@enemy".parse::<PieceMatch>().unwrap();
+        assert!(m.matches(0x0105));
+        assert!(!m.matches(0x0A00));
+        assert_eq!(m.to_string(), "!@enemy");
+
+        assert!("@nowhere".parse::<PieceMatch>().is_err());
+        assert!("01G5".parse::<PieceMatch>().is_err());
+    }
+
+    #[test]
+    fn test_set_predicates() {
+        let mut constraints = SetConstraints::new(
+            "X".parse::<PieceConstraint>().unwrap(),
+            "!0002".parse::<PieceConstraint>().unwrap(),
+            "G0402".parse::<PieceConstraint>().unwrap(),
+        );
+        constraints.predicates.push(SetPredicate::NoneMatch("@underground".parse::<PieceMatch>().unwrap()));
+
+        assert!(constraints.matches([0x0101, 0x0003, GRABBED_ID]));
+        assert!(!constraints.matches([0x0101, 0x0002, GRABBED_ID]));
+        assert!(!constraints.matches([0x0101, 0x0503, GRABBED_ID]));
+    }
+
+    #[test]
+    fn test_geometry() {
+        let piece = |id, x| Emerald {
+            id,
+            position: Vector::new(x, 0.0, 0.0),
+        };
+        let pieces = [piece(0x0101, 0.0), piece(0x0002, 300.0), piece(0x0402, 400.0)];
+
+        assert!(GeometryConstraint::MaxGap(300.0).matches::<Pc>(&pieces));
+        assert!(!GeometryConstraint::MaxGap(250.0).matches::<Pc>(&pieces));
+
+        let from_start = |length| GeometryConstraint::MaxPathLength {
+            start: Some(Vector::new(-100.0, 0.0, 0.0)),
+            length,
+        };
+        assert!(from_start(500.0).matches::<Pc>(&pieces));
+        assert!(!from_start(450.0).matches::<Pc>(&pieces));
+
+        let within = GeometryConstraint::WithinRadius {
+            center: Vector::new(200.0, 0.0, 0.0),
+            radius: 200.0,
+        };
+        assert!(within.matches::<Pc>(&pieces));
+
+        // A grabbed piece is no longer on the route.
+        let pieces = [piece(GRABBED_ID, 5000.0), pieces[1], pieces[2]];
+        assert!(GeometryConstraint::MaxGap(100.0).matches::<Pc>(&pieces));
+        assert!(within.matches::<Pc>(&pieces));
+
+        assert!(parse_vector("-800,195.5,800").is_ok());
+        assert!(parse_vector("1,2").is_err());
+    }
+}
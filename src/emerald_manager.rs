@@ -1,41 +1,20 @@
-use std::cmp::Ordering;
-use std::io::{self, Read};
+use std::io::{self, Cursor, Read};
 use std::fs::File;
 use std::path::Path;
 
-use sa2_set::{SetFile, Pc};
 #[cfg(windows)]
 use process_reader::ProcessHandle;
 
 use crate::rng::Rng;
+use crate::set_data;
 use crate::vector::Vector;
 use crate::stage_spec::{Emerald, StageSpec};
+use crate::game_files::{GameFiles, Stage};
+use crate::sort::{self, PlatformSort};
 use crate::Platform;
 
 const NUM_RNG_CALLS: u32 = 138;
 
-struct F32Cmp(f32);
-
-impl PartialEq<F32Cmp> for F32Cmp {
-    fn eq(&self, other: &Self) -> bool {
-        self.0.eq(&other.0)
-    }
-}
-
-impl Eq for F32Cmp {}
-
-impl PartialOrd<F32Cmp> for F32Cmp {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.0.partial_cmp(&other.0)
-    }
-}
-
-impl Ord for F32Cmp {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap_or(Ordering::Equal)
-    }
-}
-
 #[derive(Clone, Debug)]
 pub struct EmeraldManager {
     pub slot1_pieces: Vec<Emerald>,
@@ -53,7 +32,7 @@ impl EmeraldManager {
         where P: Platform,
               R: Read,
     {
-        let set_file = SetFile::from_read::<Pc, _>(read)?;
+        let set_file = set_data::read_set_file(read)?;
 
         let mut slot1_pieces = Vec::new();
         let mut slot2_pieces = Vec::new();
@@ -189,6 +168,14 @@ impl EmeraldManager {
         Self::from_set_file::<P, _>(file)
     }
 
+    /// Loads the stage's SET file from an install, extracted disc or disc image.
+    pub fn from_game_files<P>(game: &GameFiles, stage: Stage) -> io::Result<EmeraldManager>
+        where P: Platform,
+    {
+        let data = game.read_set(stage)?;
+        Self::from_set_file::<P, _>(Cursor::new(data))
+    }
+
     pub fn from_spec<P>(spec: StageSpec) -> EmeraldManager
         where P: Platform,
     {
@@ -212,58 +199,313 @@ impl EmeraldManager {
     pub fn gen_pieces<P>(&mut self)
         where P: Platform,
     {
-        // Generate piece 1
-        if self.p1.id != 0xFE00 {
-            let num_p1 = self.slot1_pieces.len() + self.enemy_pieces.len();
+        let grabbed = [self.p1, self.p2, self.p3].map(|piece| if piece.id == 0xFE00 { Some(piece) } else { None });
+        let lists = PieceLists {
+            slot1: &self.slot1_pieces,
+            slot2: &self.slot2_pieces,
+            slot3: &self.slot3_pieces,
+            enemies: &self.enemy_pieces,
+        };
+        let (set, removed_enemy) = generate::<P, _>(lists, self.r, grabbed, &mut ());
 
-            let p1_index = ((self.r.gen_val::<P::Consts>() as f32 / 32768.0) * num_p1 as f32) as usize;
+        if let Some(idx) = removed_enemy {
+            self.enemy_pieces.swap_remove(idx);
+        }
+        self.p1 = set.pieces[0];
+        self.p2 = set.pieces[1];
+        self.p3 = set.pieces[2];
+        self.r = set.r;
+    }
 
-            self.p1 = if p1_index < self.slot1_pieces.len() {
-                self.slot1_pieces[p1_index]
-            }
-            else {
-                self.enemy_pieces.swap_remove(p1_index - self.slot1_pieces.len())
-            };
+    pub fn gen_pieces_full<P>(&mut self, frame: u32)
+        where P: Platform,
+    {
+        for _ in 0..(frame % 1024) {
+            self.r.gen_val::<P::Consts>();
         }
+    }
+}
+
+/// A set generated from one RNG state.
+#[derive(Clone, Copy, Debug)]
+pub struct PieceSet {
+    pub pieces: [Emerald; 3],
+    /// The RNG after the calls generation made.
+    pub r: Rng,
+    /// RNG calls generation made, one per slot that was not grabbed.
+    pub calls: u32,
+}
 
-        // Generate piece 2
-        if self.p2.id != 0xFE00 {
-            let mut potential_p2: Vec<_> = self.slot2_pieces.iter().chain(self.enemy_pieces.iter()).collect();
+/// Generates the set for `r` without touching the stage, with the same result
+/// as `EmeraldManager::gen_pieces`. Grabbed slots are given like in
+/// `CandidateCache::with_grabbed`.
+pub fn gen_set<P>(spec: &StageSpec, r: Rng, grabbed: [Option<Emerald>; 3]) -> PieceSet
+    where P: Platform,
+{
+    let lists = PieceLists {
+        slot1: &spec.slot1_pieces,
+        slot2: &spec.slot2_pieces,
+        slot3: &spec.slot3_pieces,
+        enemies: &spec.enemy_pieces,
+    };
+    generate::<P, _>(lists, r, grabbed, &mut ()).0
+}
 
-            potential_p2.sort_by_key(|p| F32Cmp(p.position.distance::<P::Math>(self.p1.position)));
+/// Like `gen_set`, reporting each step to `trace`.
+pub(crate) fn gen_set_traced<P, T>(spec: &StageSpec, r: Rng, grabbed: [Option<Emerald>; 3], trace: &mut T) -> PieceSet
+    where P: Platform,
+          T: GenTrace,
+{
+    let lists = PieceLists {
+        slot1: &spec.slot1_pieces,
+        slot2: &spec.slot2_pieces,
+        slot3: &spec.slot3_pieces,
+        enemies: &spec.enemy_pieces,
+    };
+    generate::<P, _>(lists, r, grabbed, trace).0
+}
 
-            let num_p2 = self.slot2_pieces.len() + self.enemy_pieces.len();
+/// Receives the steps of generation, for `explain`.
+pub(crate) trait GenTrace {
+    /// Whether the p1 candidate list has to be gathered for `draw`.
+    const TRACING: bool = true;
 
-            let mut p2_index = (num_p2 as f32 - (((self.r.gen_val::<P::Consts>() as f32 / 32768.0) * num_p2 as f32) / 2.0)) as usize;
-            if p2_index >= num_p2 {
-                p2_index -= 1;
-            }
+    /// A slot (0 to 2) drew `rand_val` from `state` and picked `index` of
+    /// `candidates`, in the order the index applies to.
+    fn draw(&mut self, slot: usize, state: u32, rand_val: u32, index: usize, candidates: &[Emerald]);
 
-            self.p2 = *potential_p2[p2_index];
-        }
+    /// An enemy p1 was taken out of the enemy list at `enemy_index`.
+    fn removed_enemy(&mut self, enemy_index: usize);
+}
 
-        // Generate piece 3
-        if self.p3.id != 0xFE00 {
-            let mut potential_p3: Vec<_> = self.slot3_pieces.iter().collect();
+impl GenTrace for () {
+    const TRACING: bool = false;
 
-            potential_p3.sort_by_key(|p| F32Cmp((p.position - self.p2.position).cross::<P::Math>(p.position - self.p1.position).magnitude::<P::Math>()));
+    fn draw(&mut self, _: usize, _: u32, _: u32, _: usize, _: &[Emerald]) {}
 
-            let num_p3 = self.slot3_pieces.len();
-            let rand_val = self.r.gen_val::<P::Consts>();
-            let mut p3_index = (num_p3 as f32 - (((rand_val as f32 / 32768.0) * num_p3 as f32) / 2.0)) as usize;
+    fn removed_enemy(&mut self, _: usize) {}
+}
+
+#[derive(Clone, Copy)]
+struct PieceLists<'a> {
+    slot1: &'a [Emerald],
+    slot2: &'a [Emerald],
+    slot3: &'a [Emerald],
+    enemies: &'a [Emerald],
+}
+
+/// Shared by `gen_set` and `EmeraldManager::gen_pieces`. Also returns the
+/// index of the enemy an enemy p1 takes out of the enemy list.
+fn generate<P, T>(lists: PieceLists, mut r: Rng, grabbed: [Option<Emerald>; 3], trace: &mut T) -> (PieceSet, Option<usize>)
+    where P: Platform,
+          T: GenTrace,
+{
+    let mut calls = 0;
+    let mut removed_enemy = None;
+
+    // Generate piece 1
+    let p1 = match grabbed[0] {
+        Some(piece) => piece,
+        None => {
+            let num_p1 = lists.slot1.len() + lists.enemies.len();
+            let state = r.get_state();
+            let rand_val = r.gen_val::<P::Consts>();
+            let p1_index = p1_index(rand_val, num_p1);
+            calls += 1;
+            if T::TRACING {
+                let candidates: Vec<Emerald> = lists.slot1.iter().chain(lists.enemies.iter()).cloned().collect();
+                trace.draw(0, state, rand_val, p1_index, &candidates);
+            }
 
-            if p3_index >= num_p3 {
-                p3_index -= 1;
+            if p1_index < lists.slot1.len() {
+                lists.slot1[p1_index]
             }
-            self.p3 = *potential_p3[p3_index];
+            else {
+                let enemy_index = p1_index - lists.slot1.len();
+                removed_enemy = Some(enemy_index);
+                trace.removed_enemy(enemy_index);
+                lists.enemies[enemy_index]
+            }
+        }
+    };
+
+    // Generate piece 2
+    let p2 = match grabbed[1] {
+        Some(piece) => piece,
+        None => {
+            let mut potential_p2 = p2_candidates(lists.slot2, lists.enemies, removed_enemy);
+            sort_p2_candidates::<P>(&mut potential_p2, p1.position);
+
+            let state = r.get_state();
+            let rand_val = r.gen_val::<P::Consts>();
+            let p2_index = far_half_index(rand_val, potential_p2.len());
+            calls += 1;
+            trace.draw(1, state, rand_val, p2_index, &potential_p2);
+            potential_p2[p2_index]
+        }
+    };
+
+    // Generate piece 3
+    let p3 = match grabbed[2] {
+        Some(piece) => piece,
+        None => {
+            let mut potential_p3 = lists.slot3.to_vec();
+            sort_p3_candidates::<P>(&mut potential_p3, p1.position, p2.position);
+
+            let state = r.get_state();
+            let rand_val = r.gen_val::<P::Consts>();
+            let p3_index = far_half_index(rand_val, potential_p3.len());
+            calls += 1;
+            trace.draw(2, state, rand_val, p3_index, &potential_p3);
+            potential_p3[p3_index]
+        }
+    };
+
+    let set = PieceSet {
+        pieces: [p1, p2, p3],
+        r,
+        calls,
+    };
+    (set, removed_enemy)
+}
+
+/// Gathers the unsorted p2 candidates: slot 2 pieces, then the enemies in
+/// the order `swap_remove`ing `removed_enemy` leaves them in.
+pub(crate) fn p2_candidates(slot2: &[Emerald], enemies: &[Emerald], removed_enemy: Option<usize>) -> Vec<Emerald> {
+    let mut candidates = Vec::with_capacity(slot2.len() + enemies.len());
+    candidates.extend_from_slice(slot2);
+    match removed_enemy {
+        Some(idx) => {
+            let last = enemies.len() - 1;
+            candidates.extend(enemies[..last].iter().enumerate()
+                .map(|(i, &piece)| if i == idx { enemies[last] } else { piece }));
         }
+        None => candidates.extend_from_slice(enemies),
     }
+    candidates
+}
 
-    pub fn gen_pieces_full<P>(&mut self, frame: u32)
+/// Orders p2 candidates by distance to p1, nearest first, with the
+/// platform's sort deciding the order of ties.
+pub(crate) fn sort_p2_candidates<P>(candidates: &mut [Emerald], p1: Vector)
+    where P: Platform,
+{
+    sort_by_key::<P, _>(candidates, |p| p2_sort_key::<P>(p, p1));
+}
+
+/// Orders p3 candidates by the area they span with p1 and p2, smallest first.
+pub(crate) fn sort_p3_candidates<P>(candidates: &mut [Emerald], p1: Vector, p2: Vector)
+    where P: Platform,
+{
+    sort_by_key::<P, _>(candidates, |p| p3_sort_key::<P>(p, p1, p2));
+}
+
+fn sort_by_key<P, F>(candidates: &mut [Emerald], key: F)
+    where P: Platform,
+          F: Fn(&Emerald) -> f32,
+{
+    let mut keyed: Vec<(f32, Emerald)> = candidates.iter().map(|p| (key(p), *p)).collect();
+    P::Sort::sort_by(&mut keyed, |a, b| sort::compare_keys(a.0, b.0));
+    for (candidate, (_, piece)) in candidates.iter_mut().zip(keyed) {
+        *candidate = piece;
+    }
+}
+
+/// Distance from a p2 candidate to p1.
+pub(crate) fn p2_sort_key<P>(candidate: &Emerald, p1: Vector) -> f32
+    where P: Platform,
+{
+    candidate.position.distance::<P::Math>(p1)
+}
+
+/// Cross-product magnitude of a p3 candidate with p1 and p2.
+pub(crate) fn p3_sort_key<P>(candidate: &Emerald, p1: Vector, p2: Vector) -> f32
+    where P: Platform,
+{
+    (candidate.position - p2).cross::<P::Math>(candidate.position - p1).magnitude::<P::Math>()
+}
+
+/// Scales a raw RNG value to an index into the p1 candidates.
+pub(crate) fn p1_index(rand_val: u32, num: usize) -> usize {
+    ((rand_val as f32 / 32768.0) * num as f32) as usize
+}
+
+/// Scales a raw RNG value to an index into the far half of a sorted candidate
+/// list, as used for p2 and p3.
+pub(crate) fn far_half_index(rand_val: u32, num: usize) -> usize {
+    let index = (num as f32 - (((rand_val as f32 / 32768.0) * num as f32) / 2.0)) as usize;
+    if index >= num {
+        index - 1
+    }
+    else {
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use byteorder::{ByteOrder, BE};
+
+    use crate::candidate_cache::CandidateCache;
+    use crate::constraint::GRABBED_ID;
+    use crate::stage_spec::load_bundled;
+    use crate::{Gc, Pc};
+
+    fn check_gen_set<P>(spec: &StageSpec, grabbed: [Option<Emerald>; 3])
         where P: Platform,
     {
-        for _ in 0..(frame % 1024) {
-            self.r.gen_val::<P::Consts>();
+        let cache = CandidateCache::<P>::with_grabbed(spec, grabbed);
+        let expected_calls = grabbed.iter().filter(|piece| piece.is_none()).count() as u32;
+
+        for index in spec.pre_calls..spec.pre_calls + 2000 {
+            let r = Rng::at_index::<P::Consts>(index);
+            let set = gen_set::<P>(spec, r, grabbed);
+
+            let mut cached_r = r;
+            let pieces = cache.gen_pieces(&mut cached_r);
+            assert_eq!(set.pieces.map(|piece| piece.id), pieces.map(|piece| piece.id));
+            assert_eq!(set.r.get_state(), cached_r.get_state());
+            assert_eq!(set.calls, expected_calls);
+            assert_eq!(set.r.get_state(), Rng::at_index::<P::Consts>(index + set.calls).get_state());
         }
     }
+
+    #[test]
+    fn test_from_set_file() {
+        // A GameCube SET file: big-endian, with one p1 piece and one enemy
+        // holding an enemy piece.
+        let mut data = vec![0u8; 0x60];
+        data[3] = 2;
+        let mut write_object = |offset: usize, object: u16, rotation: [u16; 3], position: [f32; 3]| {
+            BE::write_u16(&mut data[offset..], object);
+            for (idx, value) in rotation.iter().enumerate() {
+                BE::write_u16(&mut data[offset + 2 + 2 * idx..], *value);
+            }
+            for (idx, value) in position.iter().enumerate() {
+                BE::write_f32(&mut data[offset + 8 + 4 * idx..], *value);
+            }
+        };
+        write_object(0x20, 0x000F, [0x0103, 0, 0], [1.0, -2.0, 300.5]);
+        write_object(0x40, 0x0038, [0, 0x0004, 0], [4.0, 5.0, 6.0]);
+
+        let em = EmeraldManager::from_set_file::<Gc, _>(data.as_slice()).unwrap();
+        assert_eq!(em.slot1_pieces.len(), 1);
+        assert_eq!(em.slot1_pieces[0].id, 0x0103);
+        assert_eq!((em.slot1_pieces[0].position.x, em.slot1_pieces[0].position.z), (1.0, 300.5));
+        assert_eq!(em.enemy_pieces.iter().map(|piece| piece.id).collect::<Vec<_>>(), [0x0A04]);
+    }
+
+    #[test]
+    fn test_gen_set() {
+        let pc_spec = load_bundled("PC/dc_spec_pc.txt");
+        let mut grabbed_p1 = pc_spec.get_emerald_by_id(0x0307).unwrap();
+        grabbed_p1.id = GRABBED_ID;
+        check_gen_set::<Pc>(&pc_spec, [None, None, None]);
+        check_gen_set::<Pc>(&pc_spec, [Some(grabbed_p1), None, None]);
+
+        let gc_spec = load_bundled("GC/mh_spec_gc.txt");
+        check_gen_set::<Gc>(&gc_spec, [None, None, None]);
+    }
 }
@@ -0,0 +1,161 @@
+use serde_derive::Serialize;
+
+use crate::emerald_manager::{gen_set_traced, p2_sort_key, p3_sort_key, GenTrace};
+use crate::rng::Rng;
+use crate::stage_spec::{Emerald, StageSpec};
+use crate::Platform;
+
+/// Every step `EmeraldManager::gen_pieces` takes for one RNG state.
+#[derive(Clone, Debug, Serialize)]
+pub struct Explanation {
+    pub start_state: u32,
+    pub p1: SlotPick,
+    /// Set when p1 came from the enemy list and was taken out of it.
+    pub removed_enemy: Option<RemovedEnemy>,
+    pub p2: SlotPick,
+    pub p3: SlotPick,
+    pub end_state: u32,
+    /// RNG calls generation made.
+    pub calls: u32,
+}
+
+/// How one slot was filled.
+#[derive(Clone, Debug, Serialize)]
+pub struct SlotPick {
+    /// The RNG draw, or `None` for a slot grabbed in a previous life.
+    pub draw: Option<Draw>,
+    /// Candidates in the order the index applies to: slot 1 pieces then
+    /// enemies for p1, sorted by `key` for p2 and p3. Empty when grabbed.
+    pub candidates: Vec<Candidate>,
+    pub piece: Emerald,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Draw {
+    /// RNG state before the call.
+    pub state: u32,
+    /// Raw `gen_val` output, 0 to 0x7FFF.
+    pub rand_val: u32,
+    /// The value scaled to an index into the candidates.
+    pub index: usize,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Candidate {
+    pub piece: Emerald,
+    /// Distance to p1 for p2, cross-product magnitude for p3, none for p1.
+    pub key: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct RemovedEnemy {
+    /// Position in the enemy list before removal.
+    pub enemy_index: usize,
+    pub piece: Emerald,
+    /// The last enemy, which `swap_remove` moved into the freed position.
+    pub moved: Option<Emerald>,
+}
+
+/// Explains set generation from `r`. Grabbed slots are given like in
+/// `CandidateCache::with_grabbed`.
+pub fn explain<P>(spec: &StageSpec, r: Rng, grabbed: [Option<Emerald>; 3]) -> Explanation
+    where P: Platform,
+{
+    let mut recorder = Recorder::default();
+    let set = gen_set_traced::<P, _>(spec, r, grabbed, &mut recorder);
+    let [p1, p2, _] = set.pieces;
+
+    let pick = |slot: usize, key: &dyn Fn(&Emerald) -> Option<f32>| match recorder.draws[slot] {
+        Some((draw, ref candidates)) => SlotPick {
+            draw: Some(draw),
+            candidates: candidates.iter()
+                .map(|piece| Candidate { piece: *piece, key: key(piece) })
+                .collect(),
+            piece: set.pieces[slot],
+        },
+        None => SlotPick {
+            draw: None,
+            candidates: Vec::new(),
+            piece: set.pieces[slot],
+        },
+    };
+
+    let removed_enemy = recorder.removed_enemy.map(|enemy_index| RemovedEnemy {
+        enemy_index,
+        piece: spec.enemy_pieces[enemy_index],
+        moved: if enemy_index + 1 < spec.enemy_pieces.len() { spec.enemy_pieces.last().cloned() } else { None },
+    });
+
+    Explanation {
+        start_state: r.get_state(),
+        p1: pick(0, &|_| None),
+        removed_enemy,
+        p2: pick(1, &|piece| Some(p2_sort_key::<P>(piece, p1.position))),
+        p3: pick(2, &|piece| Some(p3_sort_key::<P>(piece, p1.position, p2.position))),
+        end_state: set.r.get_state(),
+        calls: set.calls,
+    }
+}
+
+#[derive(Default)]
+struct Recorder {
+    draws: [Option<(Draw, Vec<Emerald>)>; 3],
+    removed_enemy: Option<usize>,
+}
+
+impl GenTrace for Recorder {
+    fn draw(&mut self, slot: usize, state: u32, rand_val: u32, index: usize, candidates: &[Emerald]) {
+        self.draws[slot] = Some((Draw { state, rand_val, index }, candidates.to_vec()));
+    }
+
+    fn removed_enemy(&mut self, enemy_index: usize) {
+        self.removed_enemy = Some(enemy_index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::candidate_cache::CandidateCache;
+    use crate::constraint::GRABBED_ID;
+    use crate::stage_spec::load_bundled;
+    use crate::Pc;
+
+    #[test]
+    fn test_explain_matches_generation() {
+        let spec = load_bundled("PC/dc_spec_pc.txt");
+        let mut grabbed_p1 = spec.get_emerald_by_id(0x0307).unwrap();
+        grabbed_p1.id = GRABBED_ID;
+
+        for &grabbed in [[None, None, None], [Some(grabbed_p1), None, None]].iter() {
+            let cache = CandidateCache::<Pc>::with_grabbed(&spec, grabbed);
+            let mut saw_enemy = false;
+
+            for index in 0..500 {
+                let r = Rng::at_index::<crate::rng::PcRng>(index);
+                let explanation = explain::<Pc>(&spec, r, grabbed);
+
+                let mut cached_r = r;
+                let pieces = cache.gen_pieces(&mut cached_r);
+                let explained = [explanation.p1.piece.id, explanation.p2.piece.id, explanation.p3.piece.id];
+                assert_eq!(explained, [pieces[0].id, pieces[1].id, pieces[2].id]);
+                assert_eq!(explanation.end_state, cached_r.get_state());
+                assert_eq!(explanation.calls, if grabbed[0].is_some() { 2 } else { 3 });
+
+                for pick in [&explanation.p2, &explanation.p3].iter() {
+                    let keys: Vec<f32> = pick.candidates.iter().map(|c| c.key.unwrap()).collect();
+                    assert!(keys.windows(2).all(|pair| pair[0] <= pair[1]));
+                }
+
+                if let Some(removed) = explanation.removed_enemy {
+                    saw_enemy = true;
+                    assert_eq!(removed.piece.id, explanation.p1.piece.id);
+                    assert_eq!(removed.piece.id, spec.enemy_pieces[removed.enemy_index].id);
+                    assert!(explanation.p2.candidates.iter().all(|c| c.piece.id != removed.piece.id));
+                }
+            }
+            assert_eq!(saw_enemy, grabbed[0].is_none());
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use std::fs::{self, File};
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+use sa2_text::Language;
+
+use crate::gcm::Gcm;
+use crate::hint_lookup::HintLookup;
+
+pub const DEFAULT_SPEC_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/spec_files");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    PumpkinHill,
+    AquaticMine,
+    SecurityHall,
+    WildCanyon,
+    DryLagoon,
+    DeathChamber,
+    EggQuarters,
+    MeteorHerd,
+    MadSpace,
+}
+
+pub const STAGES: [Stage; 9] = [
+    Stage::PumpkinHill,
+    Stage::AquaticMine,
+    Stage::SecurityHall,
+    Stage::WildCanyon,
+    Stage::DryLagoon,
+    Stage::DeathChamber,
+    Stage::EggQuarters,
+    Stage::MeteorHerd,
+    Stage::MadSpace,
+];
+
+impl Stage {
+    /// Level number used by the game in file names (setXXXX_s.bin, ehXXXXe.prs).
+    pub fn level_id(self) -> u32 {
+        match self {
+            Stage::PumpkinHill => 5,
+            Stage::AquaticMine => 7,
+            Stage::SecurityHall => 8,
+            Stage::WildCanyon => 16,
+            Stage::DryLagoon => 18,
+            Stage::DeathChamber => 25,
+            Stage::EggQuarters => 26,
+            Stage::MeteorHerd => 32,
+            Stage::MadSpace => 44,
+        }
+    }
+
+    /// Short name used by the bundled spec files (e.g. "dc" for dc_spec_pc.txt).
+    pub fn abbreviation(self) -> &'static str {
+        match self {
+            Stage::PumpkinHill => "ph",
+            Stage::AquaticMine => "am",
+            Stage::SecurityHall => "sh",
+            Stage::WildCanyon => "wc",
+            Stage::DryLagoon => "dl",
+            Stage::DeathChamber => "dc",
+            Stage::EggQuarters => "eq",
+            Stage::MeteorHerd => "mh",
+            Stage::MadSpace => "ms",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::PumpkinHill => "Pumpkin Hill",
+            Stage::AquaticMine => "Aquatic Mine",
+            Stage::SecurityHall => "Security Hall",
+            Stage::WildCanyon => "Wild Canyon",
+            Stage::DryLagoon => "Dry Lagoon",
+            Stage::DeathChamber => "Death Chamber",
+            Stage::EggQuarters => "Egg Quarters",
+            Stage::MeteorHerd => "Meteor Herd",
+            Stage::MadSpace => "Mad Space",
+        }
+    }
+
+    /// Accepts the abbreviation, the full name (case and spacing ignored) or
+    /// the level number.
+    pub fn from_name(s: &str) -> Option<Stage> {
+        let squashed: String = s.chars()
+            .filter(|c| !c.is_whitespace() && *c != '_' && *c != '-')
+            .flat_map(|c| c.to_lowercase())
+            .collect();
+        let level_id = s.parse::<u32>().ok();
+
+        STAGES.iter()
+            .cloned()
+            .find(|stage| {
+                stage.abbreviation() == squashed ||
+                stage.name().replace(' ', "").to_lowercase() == squashed ||
+                Some(stage.level_id()) == level_id
+            })
+    }
+
+    /// Candidate SET file names in lookup order. Some builds ship them
+    /// PRS-compressed with a `.prs` extension.
+    pub fn set_file_names(self) -> [String; 4] {
+        [
+            format!("set{:04}_s.bin", self.level_id()),
+            format!("set{:04}_s.prs", self.level_id()),
+            format!("set{:04}_u.bin", self.level_id()),
+            format!("set{:04}_u.prs", self.level_id()),
+        ]
+    }
+
+    pub fn hint_file_name(self, lang: HintLanguage) -> String {
+        format!("eh{:04}{}.prs", self.level_id(), lang.suffix())
+    }
+
+    /// `platform` is the lower-case platform name ("pc" or "gc").
+    pub fn spec_file_name(self, platform: &str) -> String {
+        format!("{}_spec_{}.txt", self.abbreviation(), platform)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HintLanguage {
+    Japanese,
+    #[default]
+    English,
+    French,
+    Spanish,
+    German,
+    Italian,
+}
+
+impl HintLanguage {
+    pub fn suffix(self) -> char {
+        match self {
+            HintLanguage::Japanese => 'j',
+            HintLanguage::English => 'e',
+            HintLanguage::French => 'f',
+            HintLanguage::Spanish => 's',
+            HintLanguage::German => 'g',
+            HintLanguage::Italian => 'i',
+        }
+    }
+
+    /// The text encoding of this language's message files: Shift-JIS for
+    /// Japanese, the Latin code page for the others.
+    pub fn text_language(self) -> Language {
+        match self {
+            HintLanguage::Japanese => Language::Japanese,
+            _ => Language::English,
+        }
+    }
+
+    pub fn from_code(s: &str) -> Option<HintLanguage> {
+        match s.to_lowercase().as_str() {
+            "j" | "ja" | "jp" | "japanese" => Some(HintLanguage::Japanese),
+            "e" | "en" | "english" => Some(HintLanguage::English),
+            "f" | "fr" | "french" => Some(HintLanguage::French),
+            "s" | "es" | "spanish" => Some(HintLanguage::Spanish),
+            "g" | "de" | "german" => Some(HintLanguage::German),
+            "i" | "it" | "italian" => Some(HintLanguage::Italian),
+            _ => None,
+        }
+    }
+}
+
+/// Game data of a PC install, an extracted GC disc or a GC disc image.
+#[derive(Clone, Debug)]
+pub struct GameFiles {
+    source: Source,
+}
+
+#[derive(Clone, Debug)]
+enum Source {
+    Dir(PathBuf),
+    Disc(PathBuf),
+}
+
+impl Source {
+    fn path(&self) -> &Path {
+        match *self {
+            Source::Dir(ref path) | Source::Disc(ref path) => path,
+        }
+    }
+}
+
+impl GameFiles {
+    /// Finds the stage files. `root` may be the install root (containing
+    /// `resource/gd_PC`), the `gd_PC` directory itself, the root of an
+    /// extracted GC disc (containing `files` or `root`), or an ISO/GCM image.
+    pub fn open<A>(root: A) -> io::Result<GameFiles>
+        where A: AsRef<Path>,
+    {
+        let root = root.as_ref();
+        if is_disc_image(root) {
+            // Parse the FST now so a bad image is reported here.
+            Gcm::open(root)?;
+            return Ok(GameFiles {
+                source: Source::Disc(root.to_path_buf()),
+            });
+        }
+
+        let candidates = [
+            root.join("resource").join("gd_PC"),
+            root.join("gd_PC"),
+            root.join("files"),
+            root.join("DATA").join("files"),
+            root.join("root"),
+            root.to_path_buf(),
+        ];
+
+        for dir in candidates.iter() {
+            if dir.is_dir() && contains_set_files(dir)? {
+                return Ok(GameFiles {
+                    source: Source::Dir(dir.clone()),
+                });
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("no SET files found under {}", root.display())))
+    }
+
+    /// Directory holding the stage files, or `None` for a disc image.
+    pub fn data_dir(&self) -> Option<&Path> {
+        match self.source {
+            Source::Dir(ref dir) => Some(dir),
+            Source::Disc(_) => None,
+        }
+    }
+
+    /// SET files for a stage that exist in the data directory, `_s` first.
+    pub fn set_paths(&self, stage: Stage) -> io::Result<Vec<PathBuf>> {
+        let dir = self.dir()?;
+        let mut paths = Vec::new();
+        for name in stage.set_file_names().iter() {
+            if let Some(path) = find_file(dir, name)? {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    pub fn set_path(&self, stage: Stage) -> io::Result<PathBuf> {
+        self.set_paths(stage)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| not_found(self.source.path(), &stage.set_file_names()[0]))
+    }
+
+    pub fn hint_path(&self, stage: Stage, lang: HintLanguage) -> io::Result<PathBuf> {
+        let dir = self.dir()?;
+        let name = stage.hint_file_name(lang);
+        find_file(dir, &name)?
+            .ok_or_else(|| not_found(self.source.path(), &name))
+    }
+
+    /// Contents of the stage's SET file (the `_s` file if both exist), still
+    /// compressed if it was stored that way.
+    pub fn read_set(&self, stage: Stage) -> io::Result<Vec<u8>> {
+        match self.source {
+            Source::Dir(_) => fs::read(self.set_path(stage)?),
+            Source::Disc(ref image) => {
+                let mut gcm = Gcm::open(image)?;
+                let name = stage.set_file_names()
+                    .iter()
+                    .find(|name| gcm.find(name).is_some())
+                    .cloned()
+                    .ok_or_else(|| not_found(self.source.path(), &stage.set_file_names()[0]))?;
+                gcm.read_file(&name)
+            }
+        }
+    }
+
+    /// Contents of each of the stage's SET files, `_s` then `_u`, still
+    /// compressed if they were stored that way. The game loads the objects of
+    /// both.
+    pub fn read_sets(&self, stage: Stage) -> io::Result<Vec<Vec<u8>>> {
+        let names = stage.set_file_names();
+        let mut sets = Vec::new();
+        match self.source {
+            Source::Dir(ref dir) => {
+                for variants in names.chunks(2) {
+                    for name in variants {
+                        if let Some(path) = find_file(dir, name)? {
+                            sets.push(fs::read(path)?);
+                            break;
+                        }
+                    }
+                }
+            }
+            Source::Disc(ref image) => {
+                let mut gcm = Gcm::open(image)?;
+                for variants in names.chunks(2) {
+                    if let Some(name) = variants.iter().find(|name| gcm.find(name).is_some()) {
+                        sets.push(gcm.read_file(name)?);
+                    }
+                }
+            }
+        }
+
+        if sets.is_empty() {
+            return Err(not_found(self.source.path(), &names[0]));
+        }
+        Ok(sets)
+    }
+
+    /// Contents of the stage's (still PRS-compressed) hint file.
+    pub fn read_hints(&self, stage: Stage, lang: HintLanguage) -> io::Result<Vec<u8>> {
+        match self.source {
+            Source::Dir(_) => fs::read(self.hint_path(stage, lang)?),
+            Source::Disc(ref image) => Gcm::open(image)?.read_file(&stage.hint_file_name(lang)),
+        }
+    }
+
+    fn dir(&self) -> io::Result<&Path> {
+        match self.source {
+            Source::Dir(ref dir) => Ok(dir),
+            Source::Disc(ref image) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is a disc image, not a directory", image.display()))),
+        }
+    }
+}
+
+/// Path of the bundled spec for `stage` under `spec_dir` (e.g. `spec_files/PC/dc_spec_pc.txt`).
+pub fn spec_path<A>(spec_dir: A, platform: &str, stage: Stage) -> io::Result<PathBuf>
+    where A: AsRef<Path>,
+{
+    let dir = spec_dir.as_ref().join(platform.to_uppercase());
+    let name = stage.spec_file_name(platform);
+    find_file(&dir, &name)?
+        .ok_or_else(|| not_found(&dir, &name))
+}
+
+/// Resolves a spec argument that is either a path to a spec file or a stage name.
+pub fn resolve_spec(arg: &str, platform: &str, spec_dir: Option<&str>) -> io::Result<PathBuf> {
+    let path = Path::new(arg);
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+
+    let stage = Stage::from_name(arg)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} is neither a spec file nor a stage name", arg)))?;
+    spec_path(spec_dir.unwrap_or(DEFAULT_SPEC_DIR), platform, stage)
+}
+
+/// Loads hints from an argument that is either a hint file, a game directory
+/// or a disc image.
+pub fn load_hints(arg: &str, stage: Option<Stage>, lang: HintLanguage) -> io::Result<HintLookup> {
+    let path = Path::new(arg);
+    if !path.is_dir() && !is_disc_image(path) {
+        return HintLookup::from_read(File::open(path)?, lang);
+    }
+
+    let stage = stage
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "a stage name is needed to find hints in a game directory"))?;
+    let data = GameFiles::open(path)?.read_hints(stage, lang)?;
+    HintLookup::from_read(Cursor::new(data), lang)
+}
+
+pub fn is_disc_image(path: &Path) -> bool {
+    path.is_file() &&
+        path.extension()
+            .map(|ext| ext.eq_ignore_ascii_case("iso") || ext.eq_ignore_ascii_case("gcm"))
+            .unwrap_or(false)
+}
+
+fn contains_set_files(dir: &Path) -> io::Result<bool> {
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_lowercase();
+        if name.starts_with("set") && (name.ends_with(".bin") || name.ends_with(".prs")) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Disc images and some installs use upper-case names, so match case-insensitively.
+fn find_file(dir: &Path, name: &str) -> io::Result<Option<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().eq_ignore_ascii_case(name) {
+            return Ok(Some(entry.path()));
+        }
+    }
+    Ok(None)
+}
+
+fn not_found(location: &Path, name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} not found in {}", name, location.display()))
+}
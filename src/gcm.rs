@@ -0,0 +1,290 @@
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use byteorder::{ReadBytesExt, BE};
+
+const FST_OFFSET_POS: u64 = 0x0424;
+const FST_ENTRY_SIZE: u64 = 0x0C;
+
+#[derive(Clone, Debug)]
+pub struct FstEntry {
+    /// Full path inside the disc, without a leading slash (e.g. "set0025_s.bin").
+    pub path: String,
+    pub offset: u32,
+    pub length: u32,
+}
+
+impl FstEntry {
+    pub fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+/// Read-only view of the file system in a GameCube disc image (ISO/GCM).
+pub struct Gcm<R> {
+    inner: R,
+    files: Vec<FstEntry>,
+}
+
+impl Gcm<File> {
+    pub fn open<A>(path: A) -> io::Result<Gcm<File>>
+        where A: AsRef<Path>,
+    {
+        Gcm::new(File::open(path)?)
+    }
+}
+
+impl<R> Gcm<R>
+    where R: Read + Seek,
+{
+    pub fn new(mut inner: R) -> io::Result<Gcm<R>> {
+        inner.seek(SeekFrom::Start(FST_OFFSET_POS))?;
+        let fst_offset = inner.read_u32::<BE>()? as u64;
+        let fst_size = inner.read_u32::<BE>()? as u64;
+
+        let mut fst = vec![0; fst_size as usize];
+        inner.seek(SeekFrom::Start(fst_offset))?;
+        inner.read_exact(&mut fst)?;
+
+        let files = parse_fst(&fst)?;
+
+        Ok(Gcm {
+            inner,
+            files,
+        })
+    }
+
+    pub fn files(&self) -> &[FstEntry] {
+        &self.files
+    }
+
+    /// Looks up a file by path ("dir/name.bin") or, failing that, by bare file
+    /// name anywhere on the disc. Matching ignores case.
+    pub fn find(&self, path: &str) -> Option<&FstEntry> {
+        let path = path.trim_start_matches('/');
+        self.files.iter()
+            .find(|entry| entry.path.eq_ignore_ascii_case(path))
+            .or_else(|| self.files.iter().find(|entry| entry.file_name().eq_ignore_ascii_case(path)))
+    }
+
+    pub fn read_file(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        let entry = self.find(path)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found on disc", path)))?;
+
+        let mut data = vec![0; entry.length as usize];
+        self.inner.seek(SeekFrom::Start(entry.offset as u64))?;
+        self.inner.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Same as `read_file`, wrapped so it can go straight into the `Read`-based loaders.
+    pub fn open_file(&mut self, path: &str) -> io::Result<Cursor<Vec<u8>>> {
+        self.read_file(path).map(Cursor::new)
+    }
+}
+
+fn parse_fst(fst: &[u8]) -> io::Result<Vec<FstEntry>> {
+    let mut cursor = Cursor::new(fst);
+
+    // Root entry: its length field is the total number of entries.
+    cursor.seek(SeekFrom::Start(8))?;
+    let num_entries = cursor.read_u32::<BE>()?;
+    let string_table = num_entries as u64 * FST_ENTRY_SIZE;
+    if string_table > fst.len() as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "FST entry count exceeds FST size"));
+    }
+
+    let mut files = Vec::new();
+    // (index one past the directory's last entry, path prefix)
+    let mut dirs: Vec<(u32, String)> = Vec::new();
+
+    for idx in 1..num_entries {
+        while dirs.last().map(|&(end, _)| idx >= end).unwrap_or(false) {
+            dirs.pop();
+        }
+
+        cursor.seek(SeekFrom::Start(idx as u64 * FST_ENTRY_SIZE))?;
+        let name_word = cursor.read_u32::<BE>()?;
+        let offset = cursor.read_u32::<BE>()?;
+        let length = cursor.read_u32::<BE>()?;
+
+        let is_dir = name_word >> 24 != 0;
+        let name = read_name(fst, string_table + (name_word & 0x00FFFFFF) as u64)?;
+        let path = match dirs.last() {
+            Some((_, prefix)) => format!("{}/{}", prefix, name),
+            None => name,
+        };
+
+        if is_dir {
+            dirs.push((length, path));
+        }
+        else {
+            files.push(FstEntry {
+                path,
+                offset,
+                length,
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+fn read_name(fst: &[u8], start: u64) -> io::Result<String> {
+    let bytes = fst.get(start as usize..)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "FST name offset out of range"))?;
+    let end = bytes.iter()
+        .position(|&b| b == 0)
+        .unwrap_or(bytes.len());
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use byteorder::WriteBytesExt;
+
+    enum Node {
+        File(&'static str, Vec<u8>),
+        Dir(&'static str, Vec<Node>),
+    }
+
+    fn count(nodes: &[Node]) -> u32 {
+        nodes.iter()
+            .map(|node| match node {
+                Node::File(..) => 1,
+                Node::Dir(_, children) => 1 + count(children),
+            })
+            .sum()
+    }
+
+    fn flatten(nodes: &[Node], parent: u32, entries: &mut Vec<(bool, &'static str, u32, u32)>, data: &mut Vec<Vec<u8>>) {
+        for node in nodes {
+            match node {
+                Node::File(name, contents) => {
+                    entries.push((false, name, data.len() as u32, contents.len() as u32));
+                    data.push(contents.clone());
+                }
+                Node::Dir(name, children) => {
+                    let idx = entries.len() as u32 + 1;
+                    entries.push((true, name, parent, idx + 1 + count(children)));
+                    flatten(children, idx, entries, data);
+                }
+            }
+        }
+    }
+
+    // Builds a minimal image: header, FST at 0x440, file data after it.
+    fn build_image(nodes: &[Node]) -> Vec<u8> {
+        let mut entries = Vec::new();
+        let mut data = Vec::new();
+        flatten(nodes, 0, &mut entries, &mut data);
+
+        let mut strings = Vec::new();
+        let mut name_offsets = Vec::new();
+        for &(_, name, _, _) in entries.iter() {
+            name_offsets.push(strings.len() as u32);
+            strings.extend_from_slice(name.as_bytes());
+            strings.push(0);
+        }
+
+        let fst_offset = 0x440u32;
+        let fst_size = (entries.len() as u32 + 1) * 12 + strings.len() as u32;
+        let mut data_offset = fst_offset + fst_size;
+        let mut file_offsets = Vec::new();
+        for contents in data.iter() {
+            file_offsets.push(data_offset);
+            data_offset += contents.len() as u32;
+        }
+
+        let mut image = vec![0u8; 0x424];
+        image.write_u32::<BE>(fst_offset).unwrap();
+        image.write_u32::<BE>(fst_size).unwrap();
+        image.resize(fst_offset as usize, 0);
+
+        image.write_u32::<BE>(0x01000000).unwrap();
+        image.write_u32::<BE>(0).unwrap();
+        image.write_u32::<BE>(entries.len() as u32 + 1).unwrap();
+        let mut file_idx = 0;
+        for (i, &(is_dir, _, offset, length)) in entries.iter().enumerate() {
+            let flag = if is_dir { 0x01000000 } else { 0 };
+            image.write_u32::<BE>(flag | name_offsets[i]).unwrap();
+            if is_dir {
+                image.write_u32::<BE>(offset).unwrap();
+            }
+            else {
+                image.write_u32::<BE>(file_offsets[file_idx]).unwrap();
+                file_idx += 1;
+            }
+            image.write_u32::<BE>(length).unwrap();
+        }
+        image.extend_from_slice(&strings);
+        for contents in data {
+            image.extend_from_slice(&contents);
+        }
+
+        image
+    }
+
+    #[test]
+    fn test_read_root_files() {
+        let image = build_image(&[
+            Node::File("set0025_s.bin", b"set data".to_vec()),
+            Node::File("eh0025e.prs", b"hints".to_vec()),
+        ]);
+        let mut gcm = Gcm::new(Cursor::new(image)).unwrap();
+
+        assert_eq!(gcm.files().len(), 2);
+        assert_eq!(gcm.read_file("set0025_s.bin").unwrap(), b"set data");
+        assert_eq!(gcm.read_file("/EH0025E.PRS").unwrap(), b"hints");
+        assert!(gcm.read_file("set0005_s.bin").is_err());
+    }
+
+    #[test]
+    fn test_nested_directories() {
+        let image = build_image(&[
+            Node::Dir("stages", vec![
+                Node::File("set0005_s.bin", b"pumpkin".to_vec()),
+                Node::Dir("hints", vec![
+                    Node::File("eh0005e.prs", b"ph hints".to_vec()),
+                ]),
+            ]),
+            Node::File("after.bin", b"root file".to_vec()),
+        ]);
+        let mut gcm = Gcm::new(Cursor::new(image)).unwrap();
+
+        let paths: Vec<_> = gcm.files().iter().map(|entry| entry.path.clone()).collect();
+        assert_eq!(paths, vec!["stages/set0005_s.bin", "stages/hints/eh0005e.prs", "after.bin"]);
+        assert_eq!(gcm.read_file("stages/hints/eh0005e.prs").unwrap(), b"ph hints");
+        assert_eq!(gcm.read_file("set0005_s.bin").unwrap(), b"pumpkin");
+        assert_eq!(gcm.read_file("after.bin").unwrap(), b"root file");
+    }
+
+    #[test]
+    fn test_set_file_from_image() {
+        use crate::stage_spec::StageSpec;
+
+        // Header plus one emerald object (0x000F) with piece ID 0x0302.
+        let mut set_data = Vec::new();
+        set_data.write_u32::<BE>(1).unwrap();
+        set_data.resize(0x20, 0);
+        set_data.write_u16::<BE>(0x000F).unwrap();
+        set_data.write_u16::<BE>(0x0302).unwrap();
+        set_data.write_u32::<BE>(0).unwrap();
+        set_data.write_f32::<BE>(10.0).unwrap();
+        set_data.write_f32::<BE>(-20.0).unwrap();
+        set_data.write_f32::<BE>(30.0).unwrap();
+        set_data.resize(0x40, 0);
+
+        let image = build_image(&[Node::File("set0005_s.bin", set_data)]);
+        let mut gcm = Gcm::new(Cursor::new(image)).unwrap();
+        let spec = StageSpec::from_set_read(gcm.open_file("set0005_s.bin").unwrap()).unwrap();
+
+        assert_eq!(spec.slot1_pieces.len(), 1);
+        assert_eq!(spec.slot1_pieces[0].id, 0x0302);
+        assert_eq!(spec.slot1_pieces[0].position.y, -20.0);
+    }
+}
@@ -0,0 +1,295 @@
+//! Instruction-level model of the Gekko floating-point unit.
+//!
+//! Registers hold doubles. Single-precision instructions compute the exact
+//! result and round it once to single precision, as the hardware does, so the
+//! fused multiply-adds do not round the product first. They also round the
+//! mantissa of the `c` operand to 25 bits beforehand. Denormals are kept, and
+//! everything rounds to nearest even.
+
+use std::num::Wrapping;
+use std::f64;
+
+use crate::soft_float::Exact;
+
+#[derive(Clone, Copy, Debug)]
+struct BaseAndDec {
+    base: u32,
+    dec: u32,
+}
+
+const FRSQRTE_EXPECTED: [BaseAndDec; 32] = [
+    BaseAndDec{base: 0x3ffa000, dec: 0x7a4}, BaseAndDec{base: 0x3c29000, dec: 0x700},
+    BaseAndDec{base: 0x38aa000, dec: 0x670}, BaseAndDec{base: 0x3572000, dec: 0x5f2},
+    BaseAndDec{base: 0x3279000, dec: 0x584}, BaseAndDec{base: 0x2fb7000, dec: 0x524},
+    BaseAndDec{base: 0x2d26000, dec: 0x4cc}, BaseAndDec{base: 0x2ac0000, dec: 0x47e},
+    BaseAndDec{base: 0x2881000, dec: 0x43a}, BaseAndDec{base: 0x2665000, dec: 0x3fa},
+    BaseAndDec{base: 0x2468000, dec: 0x3c2}, BaseAndDec{base: 0x2287000, dec: 0x38e},
+    BaseAndDec{base: 0x20c1000, dec: 0x35e}, BaseAndDec{base: 0x1f12000, dec: 0x332},
+    BaseAndDec{base: 0x1d79000, dec: 0x30a}, BaseAndDec{base: 0x1bf4000, dec: 0x2e6},
+    BaseAndDec{base: 0x1a7e800, dec: 0x568}, BaseAndDec{base: 0x17cb800, dec: 0x4f3},
+    BaseAndDec{base: 0x1552800, dec: 0x48d}, BaseAndDec{base: 0x130c000, dec: 0x435},
+    BaseAndDec{base: 0x10f2000, dec: 0x3e7}, BaseAndDec{base: 0x0eff000, dec: 0x3a2},
+    BaseAndDec{base: 0x0d2e000, dec: 0x365}, BaseAndDec{base: 0x0b7c000, dec: 0x32e},
+    BaseAndDec{base: 0x09e5000, dec: 0x2fc}, BaseAndDec{base: 0x0867000, dec: 0x2d0},
+    BaseAndDec{base: 0x06ff000, dec: 0x2a8}, BaseAndDec{base: 0x05ab800, dec: 0x283},
+    BaseAndDec{base: 0x046a000, dec: 0x261}, BaseAndDec{base: 0x0339800, dec: 0x243},
+    BaseAndDec{base: 0x0218800, dec: 0x226}, BaseAndDec{base: 0x0105800, dec: 0x20b},
+];
+
+const FRES_EXPECTED: [BaseAndDec; 32] = [
+    BaseAndDec{base: 0x7ff800, dec: 0x3e1}, BaseAndDec{base: 0x783800, dec: 0x3a7},
+    BaseAndDec{base: 0x70ea00, dec: 0x371}, BaseAndDec{base: 0x6a0800, dec: 0x340},
+    BaseAndDec{base: 0x638800, dec: 0x313}, BaseAndDec{base: 0x5d6200, dec: 0x2ea},
+    BaseAndDec{base: 0x579000, dec: 0x2c4}, BaseAndDec{base: 0x520800, dec: 0x2a0},
+    BaseAndDec{base: 0x4cc800, dec: 0x27f}, BaseAndDec{base: 0x47ca00, dec: 0x261},
+    BaseAndDec{base: 0x430800, dec: 0x245}, BaseAndDec{base: 0x3e8000, dec: 0x22a},
+    BaseAndDec{base: 0x3a2c00, dec: 0x212}, BaseAndDec{base: 0x360800, dec: 0x1fb},
+    BaseAndDec{base: 0x321400, dec: 0x1e5}, BaseAndDec{base: 0x2e4a00, dec: 0x1d1},
+    BaseAndDec{base: 0x2aa800, dec: 0x1be}, BaseAndDec{base: 0x272c00, dec: 0x1ac},
+    BaseAndDec{base: 0x23d600, dec: 0x19b}, BaseAndDec{base: 0x209e00, dec: 0x18b},
+    BaseAndDec{base: 0x1d8800, dec: 0x17c}, BaseAndDec{base: 0x1a9000, dec: 0x16e},
+    BaseAndDec{base: 0x17ae00, dec: 0x15b}, BaseAndDec{base: 0x14f800, dec: 0x15b},
+    BaseAndDec{base: 0x124400, dec: 0x143}, BaseAndDec{base: 0x0fbe00, dec: 0x143},
+    BaseAndDec{base: 0x0d3800, dec: 0x12d}, BaseAndDec{base: 0x0ade00, dec: 0x12d},
+    BaseAndDec{base: 0x088400, dec: 0x11a}, BaseAndDec{base: 0x065000, dec: 0x11a},
+    BaseAndDec{base: 0x041c00, dec: 0x108}, BaseAndDec{base: 0x020c00, dec: 0x106},
+];
+
+/// Reciprocal square root estimate.
+pub fn frsqrte(val: f64) -> f64 {
+    let integral = val.to_bits();
+    let sign = integral & (1 << 63);
+    let mut exponent = integral & (0x7FFu64 << 52);
+    let mantissa = integral & ((1 << 52) - 1);
+
+    if exponent == 0 && mantissa == 0 {
+        if sign == 0 {
+            return f64::INFINITY;
+        }
+        else {
+            return f64::NEG_INFINITY;
+        }
+    }
+
+    if exponent == (0x7FF << 52) {
+        if mantissa == 0 {
+            if sign == 0 {
+                return 0.0;
+            }
+            else {
+                return f64::NAN;
+            }
+        }
+        return 0.0 + val;
+    }
+
+    if sign != 0 {
+        return f64::NAN;
+    }
+
+    // Get exponent LSB before we modify it
+    let exponent_lsb = exponent & (1 << 52) ^ (1 << 52);
+
+    // Divide exponent by 2?
+    exponent = (Wrapping(0x3FF << 52) - Wrapping((Wrapping(exponent) - Wrapping(0x3FE << 52)).0 / 2)).0 & (0x7FF << 52);
+
+    // Get entry in lerp table
+    let idx = (exponent_lsb | mantissa) >> 37;
+    let entry = FRSQRTE_EXPECTED[(idx / 2048) as usize];
+
+    // Start making the result since we already have the sign/exponent
+    let mut sqrt = sign | exponent;
+
+    // Interpolate from base to next entry using dec(lination?) as the slope
+    sqrt |= ((entry.base - entry.dec * (idx as u32 % 2048)) as u64) << 26;
+
+    f64::from_bits(sqrt)
+}
+
+/// Reciprocal estimate.
+pub fn fres(val: f64) -> f64 {
+    let integral = val.to_bits();
+    let sign = integral & (1 << 63);
+    let mut exponent = integral & (0x7FFu64 << 52);
+    let mantissa = integral & ((1 << 52) - 1);
+
+    if exponent == 0 && mantissa == 0 {
+        if sign == 0 {
+            return f64::INFINITY;
+        }
+        else {
+            return f64::NEG_INFINITY;
+        }
+    }
+
+    if exponent < (895 << 52) {
+        return f64::MAX;
+    }
+
+    if exponent >= (1149 << 52) {
+        return 0.0f64;
+    }
+
+    // Negate exponent
+    exponent = (0x7FD << 52) - exponent;
+
+    // Get entry in lerp table
+    let idx = mantissa >> 37;
+    let entry = FRES_EXPECTED[(idx / 1024) as usize];
+
+    // Start making the result since we already have the sign/exponent
+    let mut inv = sign | exponent;
+
+    // Interpolate from base to next entry using dec(lination?) as the slope
+    inv |= ((entry.base - (entry.dec * (idx as u32 % 1024) + 1) / 2) as u64) << 29;
+
+    f64::from_bits(inv)
+}
+
+/// Rounds a register to single precision.
+pub fn frsp(val: f64) -> f64 {
+    val as f32 as f64
+}
+
+/// Single-precision multiply.
+pub fn fmuls(a: f64, c: f64) -> f64 {
+    let c = force_25_bit(c);
+    match (Exact::new(a), Exact::new(c)) {
+        (Some(a), Some(c)) => a.mul(c).round_single(),
+        _ => frsp(a * c),
+    }
+}
+
+/// Single-precision fused multiply-add: `a * c + b`.
+pub fn fmadds(a: f64, c: f64, b: f64) -> f64 {
+    let c = force_25_bit(c);
+    if a == frsp(a) {
+        if let Some(result) = fmadds_single(a, c, b) {
+            return result;
+        }
+    }
+    match (Exact::new(a), Exact::new(c)) {
+        (Some(a), Some(c)) if b.is_finite() => {
+            let product = a.mul(c);
+            match Exact::new(b) {
+                Some(b) => product.add(b).map_or(0.0, Exact::round_single),
+                None => product.round_single(),
+            }
+        }
+        // A zero product leaves b, and infinities and NaNs need no rounding.
+        _ => frsp(a * c + b),
+    }
+}
+
+/// `fmadds` for a single-precision `a`, the common case. The product of a
+/// single and a 25-bit `c` is exact as a double, so the sum can be rounded to
+/// odd as a double and then to single, which is the same as rounding the
+/// exact sum once.
+fn fmadds_single(a: f64, c: f64, b: f64) -> Option<f64> {
+    let product = a * c;
+    let sum = product + b;
+    if !sum.is_finite() {
+        return None;
+    }
+
+    let b_part = sum - product;
+    let error = (product - (sum - b_part)) + (b - b_part);
+    let sum = if error != 0.0 && sum.to_bits() & 1 == 0 {
+        // Step to the odd neighbour on the side of the exact sum.
+        let away_from_zero = (error > 0.0) == (sum > 0.0);
+        f64::from_bits(if away_from_zero { sum.to_bits() + 1 } else { sum.to_bits() - 1 })
+    }
+    else {
+        sum
+    };
+    Some(frsp(sum))
+}
+
+/// Single-precision fused multiply-subtract: `a * c - b`.
+pub fn fmsubs(a: f64, c: f64, b: f64) -> f64 {
+    fmadds(a, c, -b)
+}
+
+/// Single-precision negated fused multiply-subtract: `-(a * c - b)`.
+pub fn fnmsubs(a: f64, c: f64, b: f64) -> f64 {
+    -fmsubs(a, c, b)
+}
+
+/// Double-precision fused multiply-add: `a * c + b`.
+pub fn fmadd(a: f64, c: f64, b: f64) -> f64 {
+    a.mul_add(c, b)
+}
+
+/// Rounds the mantissa to 25 significant bits, half away from zero, like the
+/// multiplier does with the `c` operand of single-precision instructions.
+/// Single-precision values pass through unchanged.
+fn force_25_bit(val: f64) -> f64 {
+    let integral = val.to_bits();
+    f64::from_bits((integral & 0xFFFF_FFFF_F000_0000) + ((integral & 0x0800_0000) << 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single(bits: u32) -> f64 {
+        f32::from_bits(bits) as f64
+    }
+
+    fn assert_single(val: f64, bits: u32) {
+        assert_eq!((val as f32).to_bits(), bits);
+        assert_eq!(val, frsp(val));
+    }
+
+    #[test]
+    fn test_fmuls() {
+        // 1.5 * 1.5 and a product that rounds up
+        assert_single(fmuls(single(0x3FC00000), single(0x3FC00000)), 0x40100000);
+        assert_single(fmuls(single(0x3F800001), single(0x3F800001)), 0x3F800002);
+        // The c operand is rounded to 25 bits first: 1 + 2^-25 + 2^-52 becomes 1 + 2^-24.
+        assert_single(fmuls(single(0x3F800001), f64::from_bits(0x3FF0000008000001)), 0x3F800002);
+        assert_single(fmuls(f64::from_bits(0x3FF0000008000001), single(0x3F800001)), 0x3F800001);
+        // Denormal results and underflow to zero
+        assert_single(fmuls(single(0x00800000), single(0x3F000000)), 0x00400000);
+        assert_single(fmuls(single(0x00000001), single(0x3F000000)), 0x00000000);
+        assert_single(fmuls(single(0x00000001), single(0x3F400000)), 0x00000001);
+        // Overflow
+        assert_single(fmuls(single(0x7F7FFFFF), single(0x40000000)), 0x7F800000);
+        assert_single(fmuls(single(0x7F7FFFFF), single(0xC0000000)), 0xFF800000);
+    }
+
+    #[test]
+    fn test_fmadds() {
+        // 1 + 2^-24 + 2^-60 rounds to 1 + 2^-24 as a double, which a second
+        // rounding to single would take down to 1.
+        let b = f64::from_bits(0x3FF0000010000000);
+        let tiny = single(0x30800000);
+        assert_eq!((tiny * tiny + b) as f32, 1.0);
+        assert_single(fmadds(tiny, tiny, b), 0x3F800001);
+        assert_single(fmsubs(tiny, tiny, -b), 0x3F800001);
+        assert_single(fnmsubs(tiny, tiny, -b), 0xBF800001);
+
+        // (1 + 2^-12)^2 - 1 keeps the product's low bits through cancellation.
+        let a = single(0x3F800800);
+        assert_single(fmsubs(a, a, 1.0), 0x3A000400);
+        assert_single(fnmsubs(a, a, 1.0), 0xBA000400);
+
+        // Exact cancellation and zero products
+        assert_eq!(fmsubs(a, 1.0, a).to_bits(), 0);
+        assert_single(fmadds(0.0, a, single(0x3F800001)), 0x3F800001);
+
+        // A small addend far below the product only breaks the tie.
+        let three_halves = single(0x3FC00000);
+        let odd = single(0x3F800001);
+        assert_single(fmadds(odd, three_halves, 0.0), 0x3FC00002);
+        assert_single(fmadds(odd, three_halves, single(0x00000001)), 0x3FC00002);
+        assert_single(fmadds(odd, three_halves, single(0x80000001)), 0x3FC00001);
+    }
+
+    #[test]
+    fn test_fmadd() {
+        let a = f64::from_bits(0x3FF0000000000001);
+        assert_eq!(fmadd(a, a, -1.0).to_bits(), 0x3CC0000000000000);
+        assert_eq!(fmadd(single(0x3FC00000), single(0x3FC00000), 1.0), 3.25);
+    }
+}
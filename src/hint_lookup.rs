@@ -1,10 +1,14 @@
-use std::io::Cursor;
+use std::io::{self, Cursor, Read};
 use std::fs::File;
 use std::path::Path;
 
-use sa2_text::{Sa2TextTable, Sa2Text, TextElement, Language};
+use sa2_text::{Sa2TextTable, Sa2Text, TextElement};
 use prs_util::decoder::Decoder;
 
+use crate::constraint::{ParseConstraintError, PieceMatch};
+use crate::game_files::HintLanguage;
+use crate::stage_spec::StageSpec;
+
 trait Sa2TextExt {
     fn concat_text(&self) -> String;
 }
@@ -55,13 +59,21 @@ pub struct HintLookup {
 // 3
 // 41
 impl HintLookup {
-    pub fn from_path<P>(path: P) -> HintLookup
+    pub fn from_path<P>(path: P, lang: HintLanguage) -> HintLookup
         where P: AsRef<Path>,
     {
         let file = File::open(path).unwrap();
-        let mut decoder = Decoder::new(file);
-        let data = decoder.decode_to_vec().unwrap();
-        let table = Sa2TextTable::from_seek(Cursor::new(data), Language::English).unwrap();
+        Self::from_read(file, lang).unwrap()
+    }
+
+    /// Loads a PRS-compressed hint file from any reader (e.g. a file on a disc image).
+    /// `lang` is the language the file is in, which decides how its text is decoded.
+    pub fn from_read<R>(read: R, lang: HintLanguage) -> io::Result<HintLookup>
+        where R: Read,
+    {
+        let mut decoder = Decoder::new(read);
+        let data = decoder.decode_to_vec()?;
+        let table = Sa2TextTable::from_seek(Cursor::new(data), lang.text_language())?;
         let hints = table.texts
             .chunks(3)
             .map(|chunk| 
@@ -85,7 +97,7 @@ impl HintLookup {
         let q_final = hints[104..107].to_vec();
         let q_inenemy = hints[107..148].to_vec();
 
-        HintLookup {
+        Ok(HintLookup {
             b_normal: b_normal,
             c_normal: c_normal,
             b_hidden: b_hidden,
@@ -97,7 +109,7 @@ impl HintLookup {
             a_1p_tech: a_1p_tech,
             q_final: q_final,
             q_inenemy: q_inenemy,
-        }
+        })
     }
 
     pub fn lookup_piece(&self, id: u16) -> &Hint {
@@ -118,4 +130,118 @@ impl HintLookup {
             _ => panic!("Bad major id"),
         }
     }
+
+    /// IDs of the stage's pieces with `text` in one of their hints, ignoring
+    /// case and line breaks.
+    pub fn pieces_with_hint(&self, spec: &StageSpec, text: &str) -> Vec<u16> {
+        let text = squash(text);
+        stage_piece_ids(spec)
+            .filter(|&id| {
+                let hint = self.lookup_piece(id);
+                [&hint.h1, &hint.h2, &hint.h3].iter().any(|tier| squash(tier).contains(&text))
+            })
+            .collect()
+    }
+
+    /// Matches the pieces with `text` in one of their hints, as for
+    /// `pieces_with_hint`, or fails if there are none.
+    pub fn hint_match(&self, spec: &StageSpec, text: &str) -> Result<PieceMatch, ParseConstraintError> {
+        let ids = self.pieces_with_hint(spec, text);
+        match ids.len() {
+            0 => Err(ParseConstraintError(format!("no piece has a hint with \"{}\"", text))),
+            1 => Ok(PieceMatch::Id(ids[0])),
+            _ => Ok(PieceMatch::AnyOf(ids.into_iter().map(PieceMatch::Id).collect())),
+        }
+    }
+
+    /// The stage's pieces whose hints loosely match `query`, best first, with
+    /// their scores. The query's letters have to show up in order in one of
+    /// the hints; exact substrings, runs of letters and word starts score
+    /// higher, so "rck plr" finds "rock pillar".
+    pub fn search_hints(&self, spec: &StageSpec, query: &str) -> Vec<(u16, u32)> {
+        let query = squash(query);
+        let mut found: Vec<(u16, u32)> = stage_piece_ids(spec)
+            .filter_map(|id| {
+                let hint = self.lookup_piece(id);
+                [&hint.h1, &hint.h2, &hint.h3].iter()
+                    .filter_map(|tier| fuzzy_score(&query, &squash(tier)))
+                    .max()
+                    .map(|score| (id, score))
+            })
+            .collect();
+        found.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        found
+    }
+}
+
+/// Lowercases and collapses whitespace, line breaks included.
+fn squash(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+fn stage_piece_ids(spec: &StageSpec) -> impl Iterator<Item = u16> + '_ {
+    spec.slot1_pieces.iter()
+        .chain(spec.slot2_pieces.iter())
+        .chain(spec.slot3_pieces.iter())
+        .chain(spec.enemy_pieces.iter())
+        .map(|piece| piece.id)
+}
+
+/// Scores how well `query` matches `text`, both squashed, or `None` if its
+/// characters don't all show up in order. Spaces in the query are skipped.
+fn fuzzy_score(query: &str, text: &str) -> Option<u32> {
+    let wanted: Vec<char> = query.chars().filter(|c| *c != ' ').collect();
+    if wanted.is_empty() {
+        return None;
+    }
+    if text.contains(query) {
+        return Some(100 * wanted.len() as u32);
+    }
+
+    let mut score = 0;
+    let mut next = 0;
+    let mut prev: Option<char> = None;
+    let mut prev_matched = false;
+    for c in text.chars() {
+        if next < wanted.len() && c == wanted[next] {
+            score += 1;
+            if prev_matched {
+                score += 5;
+            }
+            if prev.is_none_or(|prev| !prev.is_alphanumeric()) {
+                score += 3;
+            }
+            next += 1;
+            prev_matched = true;
+        }
+        else {
+            prev_matched = false;
+        }
+        prev = Some(c);
+    }
+    if next == wanted.len() { Some(score) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_score() {
+        let text = squash("Look for the\nrock pillar.");
+        assert_eq!(text, "look for the rock pillar.");
+        assert_eq!(fuzzy_score("rock pillar", &text), Some(1000));
+        assert!(fuzzy_score("rck plr", &text).is_some());
+        assert!(fuzzy_score("rock pil", &text) > fuzzy_score("rck plr", &text));
+        assert_eq!(fuzzy_score("pillar rock", &text), None);
+        assert_eq!(fuzzy_score(" ", &text), None);
+    }
+
+    #[test]
+    fn test_text_language() {
+        assert!(matches!(HintLanguage::Japanese.text_language(), sa2_text::Language::Japanese));
+        for lang in [HintLanguage::English, HintLanguage::French, HintLanguage::Spanish, HintLanguage::German, HintLanguage::Italian] {
+            assert!(matches!(lang.text_language(), sa2_text::Language::English));
+        }
+    }
 }
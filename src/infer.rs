@@ -0,0 +1,383 @@
+//! Works out the RNG index from what a runner saw over several lives.
+//!
+//! Each life generates a set at some RNG index, and the next life's index is
+//! roughly known distance later. Lives only narrow pieces down (a piece seen,
+//! a hint read) and pieces collected in one life are skipped by the next, as
+//! with `SetConstraints::grabbed_pieces`. Every chain of indices consistent
+//! with all lives is weighted by how well its gaps fit, and the chains are
+//! summed up per index of the last life.
+
+use std::ops::Range;
+
+use crate::candidate_cache::CandidateCache;
+use crate::constraint::{ParseConstraintError, PieceConstraint, PieceMatch, SetConstraints};
+use crate::parallel;
+use crate::rng::Rng;
+use crate::stage_spec::StageSpec;
+use crate::Platform;
+
+/// Approximate number of RNG calls between two lives' generations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gap {
+    pub expected: u32,
+    pub tolerance: u32,
+}
+
+impl Gap {
+    /// Relative likelihood of a gap of `calls`: 1 at the expected gap, falling
+    /// linearly to 0 just past the tolerance.
+    pub fn weight(&self, calls: u64) -> f64 {
+        let distance = calls.abs_diff(self.expected as u64);
+        if distance > self.tolerance as u64 {
+            0.0
+        }
+        else {
+            1.0 - distance as f64 / (self.tolerance as f64 + 1.0)
+        }
+    }
+
+    /// The range the next index falls in if the previous one is in `window`,
+    /// clamped to the RNG's period.
+    pub fn after(&self, window: Range<u32>) -> Range<u32> {
+        let end = parallel::FULL_PERIOD - 1;
+        let start = (window.start as u64 + self.min()).min(end) as u32;
+        let stop = (window.end as u64 + self.max()).min(end) as u32;
+        start..stop
+    }
+
+    /// The runs of RNG calls the next index falls in after any of `indices`,
+    /// which have to be sorted. Overlapping windows merge into one run.
+    pub(crate) fn windows<I>(&self, indices: I) -> Vec<(u64, u64)>
+        where I: IntoIterator<Item = u32>,
+    {
+        let end = parallel::FULL_PERIOD;
+        let mut windows: Vec<(u64, u64)> = Vec::new();
+        for index in indices {
+            let start = (index as u64 + self.min()).min(end);
+            let stop = (index as u64 + self.max() + 1).min(end);
+            match windows.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(stop),
+                _ => windows.push((start, stop)),
+            }
+        }
+        windows
+    }
+
+    fn min(&self) -> u64 {
+        self.expected.saturating_sub(self.tolerance) as u64
+    }
+
+    fn max(&self) -> u64 {
+        self.expected as u64 + self.tolerance as u64
+    }
+}
+
+/// The weight of reaching an index from weighted earlier indices across a
+/// gap: the sum of each earlier weight times the gap's weight for the
+/// distance. The gap's weight is linear on either side of the expected gap,
+/// so prefix sums of the weights and of the weighted indices give it in
+/// logarithmic time, however many earlier indices are in reach.
+pub(crate) struct GapSpread {
+    gap: Gap,
+    /// Earlier indices, as offsets from the first so the sums keep their
+    /// precision.
+    base: u64,
+    offsets: Vec<i64>,
+    weights: Vec<f64>,
+    moments: Vec<f64>,
+}
+
+impl GapSpread {
+    /// `prev` holds sorted indices and their weights.
+    pub(crate) fn new(gap: Gap, prev: &[(u32, f64)]) -> GapSpread {
+        let base = prev.first().map_or(0, |&(index, _)| index as u64);
+        let offsets: Vec<i64> = prev.iter().map(|&(index, _)| (index as u64 - base) as i64).collect();
+        let mut weights = vec![0.0];
+        let mut moments = vec![0.0];
+        for (&offset, &(_, weight)) in offsets.iter().zip(prev) {
+            weights.push(weights[weights.len() - 1] + weight);
+            moments.push(moments[moments.len() - 1] + weight * offset as f64);
+        }
+        GapSpread { gap, base, offsets, weights, moments }
+    }
+
+    pub(crate) fn at(&self, index: u64) -> f64 {
+        let x = index as i64 - self.base as i64;
+        let expected = self.gap.expected as i64;
+        let position = |bound: i64| self.offsets.partition_point(|&offset| offset <= bound);
+        // Earlier indices at least the expected gap back, then the closer ones.
+        let lo = position(x - self.gap.max() as i64 - 1);
+        let mid = position(x - expected);
+        let hi = position(x - self.gap.min() as i64);
+        let sum = |values: &[f64], from: usize, to: usize| values[to] - values[from];
+
+        let scale = self.gap.tolerance as f64 + 1.0;
+        let far = (scale - (x - expected) as f64) * sum(&self.weights, lo, mid) + sum(&self.moments, lo, mid);
+        let near = (scale + (x - expected) as f64) * sum(&self.weights, mid, hi) - sum(&self.moments, mid, hi);
+        ((far + near) / scale).max(0.0)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Life {
+    /// What is known of each slot's piece this life.
+    pub seen: [Option<PieceMatch>; 3],
+    /// Pieces collected this life, which later lives don't generate.
+    pub collected: [Option<u16>; 3],
+    /// RNG calls since the previous life's set was generated. Only the first
+    /// life has none.
+    pub gap: Option<Gap>,
+}
+
+/// A possible RNG index for the last life.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub index: u32,
+    /// Share of the total weight of all consistent chains.
+    pub probability: f64,
+    /// The likeliest index of every life leading here, ending with `index`.
+    pub path: Vec<u32>,
+}
+
+/// Checks that collected pieces exist, match what was seen of their slot and
+/// aren't collected twice, and that every life after the first has a gap.
+pub fn validate(spec: &StageSpec, lives: &[Life]) -> Result<(), ParseConstraintError> {
+    let mut grabbed = [false; 3];
+    for (life_idx, life) in lives.iter().enumerate() {
+        let error = |msg: String| ParseConstraintError(format!("life {}: {}", life_idx + 1, msg));
+        if life.gap.is_some() != (life_idx > 0) {
+            return Err(error(if life_idx == 0 { "the first life has no gap".to_string() } else { "missing gap".to_string() }));
+        }
+        for (slot, grabbed) in grabbed.iter_mut().enumerate() {
+            if let Some(ref seen) = life.seen[slot] {
+                seen.validate(spec).map_err(|e| error(format!("p{}: {}", slot + 1, e)))?;
+            }
+            if let Some(id) = life.collected[slot] {
+                if *grabbed {
+                    return Err(error(format!("p{} was already collected", slot + 1)));
+                }
+                if spec.get_emerald_by_id(id).is_none() {
+                    return Err(error(format!("piece {:04X} is not present in stage", id)));
+                }
+                if life.seen[slot].as_ref().is_some_and(|seen| !seen.matches(id)) {
+                    return Err(error(format!("collected piece {:04X} does not match what was seen of p{}", id, slot + 1)));
+                }
+                *grabbed = true;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Finds the RNG indices the last life may have been generated at, likeliest
+/// first. The first life is looked for in `first`. Lives should pass
+/// `validate`.
+pub fn identify<P>(spec: &StageSpec, lives: &[Life], first: Range<u32>, jobs: usize) -> Vec<Candidate>
+    where P: Platform,
+{
+    // Per life: matching index, weight of the chains ending there, and the
+    // position of the likeliest previous index in the previous life's list.
+    let mut steps: Vec<Vec<(u32, f64, usize)>> = Vec::new();
+    let mut collected: [Option<u16>; 3] = [None; 3];
+
+    for life in lives {
+        let constraints = life_constraints(life, collected);
+        let cache = CandidateCache::<P>::with_grabbed(spec, constraints.grabbed_pieces(spec));
+        let matches = |r: &mut Rng| constraints.matches(cache.gen_pieces(r).map(|piece| piece.id));
+
+        let step = match (steps.last(), life.gap) {
+            (Some(prev), Some(gap)) => follow::<P, _>(prev, gap, jobs, matches),
+            _ => {
+                let mut found = Vec::new();
+                parallel::scan::<P::Consts, _, _, _>(first.start as u64, first.end as u64, jobs, |mut r| {
+                    if matches(&mut r) { Some(()) } else { None }
+                }, |index, ()| found.push((index as u32, 1.0, 0)));
+                found
+            }
+        };
+        steps.push(step);
+
+        for (slot, id) in collected.iter_mut().zip(life.collected.iter()) {
+            if id.is_some() {
+                *slot = *id;
+            }
+        }
+    }
+
+    let last = match steps.last() {
+        Some(last) => last,
+        None => return Vec::new(),
+    };
+    let total: f64 = last.iter().map(|&(_, weight, _)| weight).sum();
+    let mut candidates: Vec<Candidate> = last.iter()
+        .enumerate()
+        .map(|(pos, &(index, weight, _))| {
+            let mut path = vec![index];
+            let mut pos = pos;
+            for life in (1..steps.len()).rev() {
+                pos = steps[life][pos].2;
+                path.push(steps[life - 1][pos].0);
+            }
+            path.reverse();
+            Candidate {
+                index,
+                probability: weight / total,
+                path,
+            }
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.probability.total_cmp(&a.probability).then(a.index.cmp(&b.index)));
+    candidates
+}
+
+/// The constraints for one life, with the pieces collected before it grabbed.
+/// A piece collected this life pins its slot even if nothing was noted as
+/// seen there.
+fn life_constraints(life: &Life, collected: [Option<u16>; 3]) -> SetConstraints {
+    let slot = |idx: usize| match (collected[idx], life.collected[idx], &life.seen[idx]) {
+        (Some(id), _, _) => PieceConstraint::GrabbedId(id),
+        (None, Some(id), _) => PieceConstraint::Want(PieceMatch::Id(id)),
+        (None, None, Some(seen)) => PieceConstraint::Want(seen.clone()),
+        (None, None, None) => PieceConstraint::DontCare,
+    };
+    SetConstraints::new(slot(0), slot(1), slot(2))
+}
+
+/// Matches of the next life within the gap of the previous life's matches,
+/// weighted by the chains leading to them.
+fn follow<P, F>(prev: &[(u32, f64, usize)], gap: Gap, jobs: usize, matches: F) -> Vec<(u32, f64, usize)>
+    where P: Platform,
+          F: Fn(&mut Rng) -> bool + Sync,
+{
+    let windows = gap.windows(prev.iter().map(|&(index, _, _)| index));
+    let mut next = Vec::new();
+    parallel::scan_ranges::<P::Consts, _, _, _>(&windows, jobs, |mut r| {
+        if matches(&mut r) { Some(()) } else { None }
+    }, |index, ()| next.push(index));
+
+    let mut step: Vec<(u32, f64, usize)> = next.into_iter()
+        .filter_map(|index| {
+            // Previous indices within the gap of this one.
+            let first = prev.partition_point(|&(prev_index, _, _)| prev_index as u64 + gap.max() < index);
+            let mut weight = 0.0;
+            let mut best: Option<(usize, f64)> = None;
+            for (pos, &(prev_index, prev_weight, _)) in prev.iter().enumerate().skip(first) {
+                if prev_index as u64 + gap.min() > index {
+                    break;
+                }
+                let chain = prev_weight * gap.weight(index - prev_index as u64);
+                weight += chain;
+                if best.is_none_or(|(_, best_chain)| chain > best_chain) {
+                    best = Some((pos, chain));
+                }
+            }
+            let (pos, _) = best.filter(|_| weight > 0.0)?;
+            Some((index as u32, weight, pos))
+        })
+        .collect();
+
+    // Keep the weights from shrinking towards zero over many lives.
+    let total: f64 = step.iter().map(|&(_, weight, _)| weight).sum();
+    for entry in step.iter_mut() {
+        entry.1 /= total;
+    }
+    step
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::emerald_manager::gen_set;
+    use crate::rng::PcRng;
+    use crate::stage_spec::load_bundled;
+    use crate::Pc;
+
+    #[test]
+    fn test_gap_weight() {
+        let gap = Gap { expected: 100, tolerance: 3 };
+        assert_eq!(gap.weight(100), 1.0);
+        assert_eq!(gap.weight(98), 0.5);
+        assert_eq!(gap.weight(103), 0.25);
+        assert_eq!(gap.weight(104), 0.0);
+        assert_eq!((gap.min(), gap.max()), (97, 103));
+        assert_eq!(Gap { expected: 1, tolerance: 3 }.min(), 0);
+        assert_eq!(gap.after(1000..2000), 1097..2103);
+        assert_eq!(gap.after(u32::MAX - 10..u32::MAX), u32::MAX..u32::MAX);
+        assert_eq!(gap.windows([1000, 1005, 2000]), [(1097, 1109), (2097, 2104)]);
+    }
+
+    #[test]
+    fn test_gap_spread() {
+        let prev = [(1000, 0.5), (1002, 1.0), (1010, 0.25), (1200, 2.0)];
+        for gap in [Gap { expected: 100, tolerance: 3 }, Gap { expected: 2, tolerance: 5 }, Gap { expected: 0, tolerance: 0 }] {
+            let spread = GapSpread::new(gap, &prev);
+            for index in 990..1320 {
+                let expected: f64 = prev.iter()
+                    .filter(|&&(prev_index, _)| prev_index as u64 <= index)
+                    .map(|&(prev_index, weight)| weight * gap.weight(index - prev_index as u64))
+                    .sum();
+                assert!((spread.at(index) - expected).abs() < 1e-9, "{:?} at {}: {} != {}", gap, index, spread.at(index), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_life_constraints() {
+        let life = Life {
+            seen: [None, Some(PieceMatch::Id(0x0203)), None],
+            collected: [Some(0x0105), None, Some(0x0701)],
+            gap: None,
+        };
+        let constraints = life_constraints(&life, [None, None, Some(0x0402)]);
+        assert!(matches!(constraints.slots[0], PieceConstraint::Want(PieceMatch::Id(0x0105))));
+        assert!(matches!(constraints.slots[1], PieceConstraint::Want(PieceMatch::Id(0x0203))));
+        assert!(matches!(constraints.slots[2], PieceConstraint::GrabbedId(0x0402)));
+        assert!(!constraints.matches([0x0106, 0x0203, 0x0402]));
+    }
+
+    #[test]
+    fn test_identify() {
+        let spec = load_bundled("PC/dc_spec_pc.txt");
+
+        // A first life where all pieces are found and p1 is collected, then a
+        // second life 2400 calls later where p2 and p3 are found.
+        let (first, second) = (300_000, 302_400);
+        let pieces = gen_set::<Pc>(&spec, Rng::at_index::<PcRng>(first), [None, None, None]).pieces;
+        let mut grabbed = spec.get_emerald_by_id(pieces[0].id).unwrap();
+        grabbed.id = crate::constraint::GRABBED_ID;
+        let later = gen_set::<Pc>(&spec, Rng::at_index::<PcRng>(second), [Some(grabbed), None, None]).pieces;
+
+        let lives = [
+            Life {
+                seen: pieces.map(|piece| Some(PieceMatch::Id(piece.id))),
+                collected: [Some(pieces[0].id), None, None],
+                gap: None,
+            },
+            Life {
+                seen: [None, Some(PieceMatch::Id(later[1].id)), Some(PieceMatch::Id(later[2].id))],
+                collected: [None, None, None],
+                gap: Some(Gap { expected: 2400, tolerance: 100 }),
+            },
+        ];
+        validate(&spec, &lives).unwrap();
+
+        let candidates = identify::<Pc>(&spec, &lives, 0..1_000_000, 1);
+        assert_eq!(candidates[0].index, second);
+        assert_eq!(candidates[0].path, [first, second]);
+        let total: f64 = candidates.iter().map(|candidate| candidate.probability).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        assert!(candidates.windows(2).all(|pair| pair[0].probability >= pair[1].probability));
+
+        // Collecting p1 again, or something other than what was seen, is refused.
+        let mut twice = lives.clone();
+        twice[1].collected[0] = Some(pieces[0].id);
+        assert!(validate(&spec, &twice).is_err());
+        let mut mismatched = lives.clone();
+        mismatched[0].collected[2] = Some(later[1].id);
+        assert!(validate(&spec, &mismatched).is_err());
+        let mut no_gap = lives.clone();
+        no_gap[1].gap = None;
+        assert!(validate(&spec, &no_gap).is_err());
+    }
+}
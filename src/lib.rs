@@ -1,12 +1,36 @@
+use std::marker::PhantomData;
+
 pub mod rng;
 pub mod emerald_manager;
 pub mod vector;
+pub mod gekko;
+pub mod x87;
+mod soft_float;
 pub mod stage_spec;
 pub mod hint_lookup;
+pub mod game_files;
+pub mod gcm;
+pub mod set_data;
+pub mod constraint;
+pub mod candidate_cache;
+pub mod explain;
+pub mod parallel;
+pub mod set_index;
+pub mod table;
+pub mod sensitivity;
+pub mod calibrate;
+pub mod load_model;
+pub mod infer;
+pub mod posterior;
+pub mod sort;
 
 pub trait Platform {
     type Math: vector::PlatformMath;
     type Consts: rng::RngConsts;
+    type Sort: sort::PlatformSort;
+    /// Math models the game could plausibly compute with on this platform,
+    /// which `sensitivity` compares against.
+    const MATH_MODELS: &'static [sensitivity::MathModel];
 }
 
 pub struct Gc;
@@ -14,6 +38,8 @@ pub struct Gc;
 impl Platform for Gc {
     type Math = vector::GcFp;
     type Consts = rng::GcRng;
+    type Sort = sort::StableSort;
+    const MATH_MODELS: &'static [sensitivity::MathModel] = sensitivity::GC_MATH_MODELS;
 }
 
 pub struct Pc;
@@ -21,4 +47,49 @@ pub struct Pc;
 impl Platform for Pc {
     type Math = vector::PcFp;
     type Consts = rng::PcRng;
+    type Sort = sort::StableSort;
+    const MATH_MODELS: &'static [sensitivity::MathModel] = sensitivity::PC_MATH_MODELS;
+}
+
+// `Pc` does its math in f32, which is only what the game computes while it
+// runs the x87 at 24 bits.
+const _: () = assert!(x87::PC_PRECISION == x87::SINGLE);
+
+/// PC with the x87 model of its floating-point math at `BITS` bits of
+/// precision instead of f32 arithmetic. `PcX87<{ x87::PC_PRECISION }>` gives
+/// the same results as `Pc`, only slower.
+pub struct PcX87<const BITS: i32>;
+
+impl<const BITS: i32> Platform for PcX87<BITS> {
+    type Math = vector::PcX87Fp<BITS>;
+    type Consts = rng::PcRng;
+    type Sort = sort::StableSort;
+    const MATH_MODELS: &'static [sensitivity::MathModel] = sensitivity::PC_MATH_MODELS;
+}
+
+/// `P` with its math done by `M` instead, for comparing math models.
+pub struct WithMath<P, M>(PhantomData<fn() -> (P, M)>);
+
+impl<P, M> Platform for WithMath<P, M>
+    where P: Platform,
+          M: vector::PlatformMath,
+{
+    type Math = M;
+    type Consts = P::Consts;
+    type Sort = P::Sort;
+    const MATH_MODELS: &'static [sensitivity::MathModel] = P::MATH_MODELS;
+}
+
+/// `P` with candidates sorted by `S` instead, e.g. to try `sort::MsvcQsort`
+/// against a set with tied candidates.
+pub struct WithSort<P, S>(PhantomData<fn() -> (P, S)>);
+
+impl<P, S> Platform for WithSort<P, S>
+    where P: Platform,
+          S: sort::PlatformSort,
+{
+    type Math = P::Math;
+    type Consts = P::Consts;
+    type Sort = S;
+    const MATH_MODELS: &'static [sensitivity::MathModel] = P::MATH_MODELS;
 }
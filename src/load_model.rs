@@ -0,0 +1,147 @@
+//! Adds up a stage's pre-calls under a model of the `rand()` calls its
+//! objects make.
+//!
+//! Loading a stage creates every object in its SET files, and some object
+//! types call `rand()` while initializing. The RNG index when pieces are
+//! generated would then be the calls the game makes regardless of the layout
+//! plus the calls of each object. A model gives those counts as JSON:
+//!
+//! ```json
+//! {"base_calls": 120, "objects": [
+//!   {"object": 56, "calls": 1, "name": "enemy"},
+//!   {"object": 83, "level": 25, "calls": 2}
+//! ]}
+//! ```
+//!
+//! Object IDs index each level's object list, so the same ID can be another
+//! type in another level. An entry with a `level` overrides one without for
+//! that level. Objects the model has no entry for are assumed not to call
+//! `rand()`, and are listed in the prediction so they can be looked into.
+//!
+//! This is only the arithmetic. No model is bundled, and which object types
+//! call `rand()`, and how often, hasn't been measured for either version, so
+//! nothing here has been shown to reproduce the game's `pre_calls`. Once
+//! per-object counts are known, the models belong next to the specs, with a
+//! test that they reproduce those specs' `pre_calls`.
+
+use std::collections::BTreeMap;
+
+use sa2_set::SetFile;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LoadModel {
+    /// Calls made during boot and load that do not depend on the layout.
+    pub base_calls: u32,
+    pub objects: Vec<ObjectCalls>,
+}
+
+/// `rand()` calls one object of a type makes while loading.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ObjectCalls {
+    pub object: u16,
+    /// Level number the entry is for, or every level if absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<u32>,
+    pub calls: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Objects of one type in a layout and the calls they account for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjectTally {
+    pub object: u16,
+    pub count: u32,
+    /// Calls per object, or `None` when the model does not know the type.
+    pub calls_each: Option<u32>,
+}
+
+impl ObjectTally {
+    pub fn calls(&self) -> u32 {
+        self.count * self.calls_each.unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallCount {
+    pub pre_calls: u32,
+    /// Every object type in the layout, by ID.
+    pub objects: Vec<ObjectTally>,
+}
+
+impl CallCount {
+    /// Object types counted as making no calls for lack of data.
+    pub fn unknown(&self) -> impl Iterator<Item = &ObjectTally> {
+        self.objects.iter().filter(|tally| tally.calls_each.is_none())
+    }
+}
+
+impl LoadModel {
+    /// Calls an object of type `object` makes in level `level`, if known. A
+    /// `level` of `None` only matches entries for every level.
+    pub fn calls_for(&self, level: Option<u32>, object: u16) -> Option<u32> {
+        let specific = self.objects.iter()
+            .find(|entry| entry.object == object && entry.level.is_some() && entry.level == level);
+        let general = || self.objects.iter()
+            .find(|entry| entry.object == object && entry.level.is_none());
+        specific.or_else(general).map(|entry| entry.calls)
+    }
+
+    /// Pre-calls under this model for a level loading objects with the given
+    /// IDs, e.g. from `object_ids`.
+    pub fn count_calls<I>(&self, level: Option<u32>, object_ids: I) -> CallCount
+        where I: IntoIterator<Item = u16>,
+    {
+        let mut counts = BTreeMap::new();
+        for object in object_ids {
+            *counts.entry(object).or_insert(0) += 1;
+        }
+
+        let objects: Vec<ObjectTally> = counts.into_iter()
+            .map(|(object, count)| ObjectTally {
+                object,
+                count,
+                calls_each: self.calls_for(level, object),
+            })
+            .collect();
+
+        CallCount {
+            pre_calls: self.base_calls + objects.iter().map(ObjectTally::calls).sum::<u32>(),
+            objects,
+        }
+    }
+}
+
+/// IDs of every object in the SET files.
+pub fn object_ids(sets: &[SetFile]) -> impl Iterator<Item = u16> + '_ {
+    sets.iter().flat_map(|set| set.0.iter().map(|object| object.object.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_calls() {
+        let model: LoadModel = serde_json::from_str(r#"{"base_calls": 100, "objects": [
+            {"object": 56, "calls": 1},
+            {"object": 56, "level": 25, "calls": 3},
+            {"object": 15, "calls": 0, "name": "emerald"}
+        ]}"#).unwrap();
+        let ids = [15, 56, 56, 7, 56];
+
+        let count = model.count_calls(Some(5), ids);
+        assert_eq!(count.pre_calls, 103);
+        assert_eq!(count.objects, [
+            ObjectTally { object: 7, count: 1, calls_each: None },
+            ObjectTally { object: 15, count: 1, calls_each: Some(0) },
+            ObjectTally { object: 56, count: 3, calls_each: Some(1) },
+        ]);
+        assert_eq!(count.unknown().map(|tally| tally.object).collect::<Vec<_>>(), [7]);
+
+        assert_eq!(model.count_calls(Some(25), ids).pre_calls, 109);
+        assert_eq!(model.count_calls(None, ids).pre_calls, 103);
+        assert_eq!(LoadModel::default().count_calls(Some(25), ids).pre_calls, 0);
+    }
+}
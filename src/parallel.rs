@@ -0,0 +1,188 @@
+use std::thread;
+
+use crate::rng::{Rng, RngConsts};
+
+/// Indices each job evaluates per batch. Results are buffered one batch at a
+/// time, so this bounds memory use on long or unbounded scans.
+pub const BATCH_PER_JOB: u64 = 1 << 16;
+
+/// One past the last RNG index; both LCGs repeat after 2^32 calls.
+pub const FULL_PERIOD: u64 = 1 << 32;
+
+/// Evaluates every RNG index in `begin..end` on `jobs` threads and passes the
+/// results to `emit` in index order, independent of the number of jobs.
+///
+/// Each thread jumps its RNG straight to the start of its chunk, so the range
+/// splits into independent pieces.
+pub fn scan<R, T, F, G>(begin: u64, end: u64, jobs: usize, eval: F, mut emit: G)
+    where R: RngConsts,
+          T: Send,
+          F: Fn(Rng) -> Option<T> + Sync,
+          G: FnMut(u64, T),
+{
+    let jobs = jobs.max(1) as u64;
+    let end = end.min(FULL_PERIOD);
+    let mut batch_start = begin;
+
+    while batch_start < end {
+        let batch_end = end.min(batch_start + jobs * BATCH_PER_JOB);
+        let chunk = (batch_end - batch_start).div_ceil(jobs);
+
+        let results: Vec<Vec<(u64, T)>> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..jobs)
+                .map(|job| {
+                    let start = (batch_start + job * chunk).min(batch_end);
+                    let stop = (start + chunk).min(batch_end);
+                    let eval = &eval;
+                    scope.spawn(move || {
+                        let mut r = Rng::at_index::<R>(start as u32);
+                        let mut found = Vec::new();
+                        for index in start..stop {
+                            if let Some(result) = eval(r) {
+                                found.push((index, result));
+                            }
+                            r.gen_val::<R>();
+                        }
+                        found
+                    })
+                })
+                .collect();
+
+            handles.into_iter()
+                .map(|handle| handle.join().expect("Search thread panicked"))
+                .collect()
+        });
+
+        for (index, result) in results.into_iter().flatten() {
+            emit(index, result);
+        }
+        batch_start = batch_end;
+    }
+}
+
+/// Like `scan`, over several sorted, non-overlapping ranges of indices at
+/// once. The indices are split evenly between the jobs wherever the ranges
+/// fall, so a few long ranges and many short ones both keep every job busy.
+/// Results are buffered one batch at a time, as in `scan`.
+pub fn scan_ranges<R, T, F, G>(ranges: &[(u64, u64)], jobs: usize, eval: F, mut emit: G)
+    where R: RngConsts,
+          T: Send,
+          F: Fn(Rng) -> Option<T> + Sync,
+          G: FnMut(u64, T),
+{
+    let jobs = jobs.max(1) as u64;
+    for batch in cut(ranges, jobs * BATCH_PER_JOB) {
+        let total: u64 = batch.iter().map(|&(start, stop)| stop - start).sum();
+        let parts = cut(&batch, total.div_ceil(jobs));
+
+        let results: Vec<Vec<(u64, T)>> = thread::scope(|scope| {
+            let handles: Vec<_> = parts.iter()
+                .map(|part| {
+                    let eval = &eval;
+                    scope.spawn(move || {
+                        let mut found = Vec::new();
+                        for &(start, stop) in part {
+                            let mut r = Rng::at_index::<R>(start as u32);
+                            for index in start..stop {
+                                if let Some(result) = eval(r) {
+                                    found.push((index, result));
+                                }
+                                r.gen_val::<R>();
+                            }
+                        }
+                        found
+                    })
+                })
+                .collect();
+
+            handles.into_iter()
+                .map(|handle| handle.join().expect("Search thread panicked"))
+                .collect()
+        });
+
+        for (index, result) in results.into_iter().flatten() {
+            emit(index, result);
+        }
+    }
+}
+
+/// Cuts ranges into parts of `size` indices, the last one possibly shorter.
+fn cut(ranges: &[(u64, u64)], size: u64) -> Vec<Vec<(u64, u64)>> {
+    let size = size.max(1);
+    let mut parts: Vec<Vec<(u64, u64)>> = Vec::new();
+    let mut filled = size;
+    for &(mut start, stop) in ranges {
+        let stop = stop.min(FULL_PERIOD);
+        while start < stop {
+            if filled == size {
+                parts.push(Vec::new());
+                filled = 0;
+            }
+            let part_stop = stop.min(start + size - filled);
+            parts.last_mut().unwrap().push((start, part_stop));
+            filled += part_stop - start;
+            start = part_stop;
+        }
+    }
+    parts
+}
+
+/// Default for `--jobs`: one per available CPU.
+pub fn default_jobs() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rng::PcRng;
+
+    #[test]
+    fn test_scan_is_ordered_and_job_independent() {
+        let collect = |jobs| {
+            let mut found = Vec::new();
+            scan::<PcRng, _, _, _>(1000, 1000 + 3 * BATCH_PER_JOB + 17, jobs, |mut r| {
+                let val = r.gen_val::<PcRng>();
+                if val % 7 == 0 { Some(val) } else { None }
+            }, |index, val| found.push((index, val)));
+            found
+        };
+
+        let single = collect(1);
+        assert!(!single.is_empty());
+        assert!(single.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(collect(3), single);
+        assert_eq!(collect(8), single);
+
+        let (index, val) = single[0];
+        assert_eq!(Rng::at_index::<PcRng>(index as u32).gen_val::<PcRng>(), val);
+    }
+
+    #[test]
+    fn test_scan_ranges() {
+        let ranges = [(5, 5), (10, 2000), (2500, 2503), (9000, 40000), (50000, 50000 + 2 * BATCH_PER_JOB + 5)];
+        let collect = |jobs| {
+            let mut found = Vec::new();
+            scan_ranges::<PcRng, _, _, _>(&ranges, jobs, |mut r| {
+                let val = r.gen_val::<PcRng>();
+                if val % 7 == 0 { Some(val) } else { None }
+            }, |index, val| found.push((index, val)));
+            found
+        };
+
+        let mut expected = Vec::new();
+        for &(start, stop) in ranges.iter() {
+            scan::<PcRng, _, _, _>(start, stop, 1, |mut r| {
+                let val = r.gen_val::<PcRng>();
+                if val % 7 == 0 { Some(val) } else { None }
+            }, |index, val| expected.push((index, val)));
+        }
+        assert!(!expected.is_empty());
+        assert_eq!(collect(1), expected);
+        assert_eq!(collect(3), expected);
+        assert_eq!(collect(64), expected);
+    }
+}
@@ -0,0 +1,324 @@
+//! Tracks which sets are still possible in a window of RNG indices as
+//! evidence comes in during a life.
+//!
+//! Every index in the window is equally likely to start with, so each set is
+//! weighted by how many indices generate it. Evidence then scales the weights:
+//! seeing a hint or finding a piece rules out the sets that disagree, and
+//! checking a location and finding it empty rules out the sets with a piece
+//! there, unless the piece could have been overlooked. The chance of a piece
+//! at each location is the share of the weight of the sets with one there.
+//!
+//! The next life starts from the indices the evidence left possible, each
+//! spread over the gap to the next life's index like the chains of `infer`,
+//! so what was learnt in earlier lives keeps narrowing the sets down.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::candidate_cache::CandidateCache;
+use crate::constraint::{PieceMatch, GRABBED_ID};
+use crate::infer::{Gap, GapSpread};
+use crate::parallel;
+use crate::stage_spec::{Emerald, StageSpec};
+use crate::Platform;
+
+#[derive(Clone, Debug)]
+pub enum Evidence {
+    /// A slot's piece matches, e.g. because of its hint. Slots count from 0.
+    Slot(usize, PieceMatch),
+    /// The piece was found, in whichever slot.
+    Found(u16),
+    /// The piece's location was checked and nothing was there. `miss_rate` is
+    /// the chance a piece there would have been overlooked; 0 rules out every
+    /// set with the piece.
+    Empty { id: u16, miss_rate: f64 },
+}
+
+impl Evidence {
+    /// Factor the evidence scales a set's weight by.
+    fn likelihood(&self, ids: [u16; 3]) -> f64 {
+        let matched = match *self {
+            Evidence::Slot(slot, ref m) => ids[slot] != GRABBED_ID && m.matches(ids[slot]),
+            Evidence::Found(id) => ids.contains(&id),
+            Evidence::Empty { id, miss_rate } => return if ids.contains(&id) { miss_rate } else { 1.0 },
+        };
+        if matched { 1.0 } else { 0.0 }
+    }
+}
+
+/// A set that the window can generate.
+#[derive(Clone, Copy, Debug)]
+pub struct PossibleSet {
+    pub pieces: [Emerald; 3],
+    /// Number of indices in the window that generate it.
+    pub count: u64,
+    /// First index in the window that generates it.
+    pub first_index: u32,
+    /// Sum of the starting weights of those indices.
+    prior: f64,
+    weight: f64,
+}
+
+impl PossibleSet {
+    pub fn ids(&self) -> [u16; 3] {
+        self.pieces.map(|piece| piece.id)
+    }
+}
+
+/// Chance of a piece at one location.
+#[derive(Clone, Copy, Debug)]
+pub struct Location {
+    pub piece: Emerald,
+    pub probability: f64,
+}
+
+/// An index the set may be generated at, with its starting weight.
+#[derive(Clone, Copy, Debug)]
+struct Start {
+    index: u32,
+    /// Position of the generated set in `Posterior::sets`.
+    set: usize,
+    prior: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Posterior {
+    sets: Vec<PossibleSet>,
+    /// Every index with a starting weight, in order.
+    starts: Vec<Start>,
+    evidence: Vec<Evidence>,
+}
+
+impl Posterior {
+    /// Starts from every index in `window` being equally likely. Grabbed
+    /// slots are given like in `CandidateCache::with_grabbed`.
+    pub fn new<P>(spec: &StageSpec, grabbed: [Option<Emerald>; 3], window: Range<u32>, jobs: usize) -> Posterior
+        where P: Platform,
+    {
+        Self::generate::<P, _>(spec, grabbed, &[(window.start as u64, window.end as u64)], jobs, |_| 1.0)
+    }
+
+    /// Starts the next life, generated `gap` RNG calls after this one, with
+    /// the pieces collected so far grabbed. Each index is as likely as the
+    /// chains through this life's possible indices leading to it.
+    pub fn next_life<P>(&self, spec: &StageSpec, grabbed: [Option<Emerald>; 3], gap: Gap, jobs: usize) -> Posterior
+        where P: Platform,
+    {
+        let likelihoods: Vec<f64> = self.sets.iter().map(|set| self.likelihood(set.ids())).collect();
+        let prev: Vec<(u32, f64)> = self.starts.iter()
+            .map(|start| (start.index, start.prior * likelihoods[start.set]))
+            .filter(|&(_, weight)| weight > 0.0)
+            .collect();
+        let total: f64 = prev.iter().map(|&(_, weight)| weight).sum();
+        let prev: Vec<(u32, f64)> = prev.into_iter().map(|(index, weight)| (index, weight / total)).collect();
+
+        let spread = GapSpread::new(gap, &prev);
+        let windows = gap.windows(prev.iter().map(|&(index, _)| index));
+        Self::generate::<P, _>(spec, grabbed, &windows, jobs, |index| spread.at(index))
+    }
+
+    fn generate<P, F>(spec: &StageSpec, grabbed: [Option<Emerald>; 3], ranges: &[(u64, u64)], jobs: usize, prior: F) -> Posterior
+        where P: Platform,
+              F: Fn(u64) -> f64,
+    {
+        let cache = CandidateCache::<P>::with_grabbed(spec, grabbed);
+        let mut positions: HashMap<[u16; 3], usize> = HashMap::new();
+        let mut sets: Vec<PossibleSet> = Vec::new();
+        let mut starts: Vec<Start> = Vec::new();
+
+        parallel::scan_ranges::<P::Consts, _, _, _>(ranges, jobs, |mut r| Some(cache.gen_pieces(&mut r)), |index, pieces| {
+            let weight = prior(index);
+            if weight <= 0.0 {
+                return;
+            }
+            let ids = pieces.map(|piece| piece.id);
+            let pos = *positions.entry(ids).or_insert_with(|| {
+                sets.push(PossibleSet {
+                    pieces,
+                    count: 0,
+                    first_index: index as u32,
+                    prior: 0.0,
+                    weight: 0.0,
+                });
+                sets.len() - 1
+            });
+            sets[pos].count += 1;
+            sets[pos].prior += weight;
+            starts.push(Start { index: index as u32, set: pos, prior: weight });
+        });
+
+        let mut posterior = Posterior {
+            sets,
+            starts,
+            evidence: Vec::new(),
+        };
+        posterior.reweigh();
+        posterior
+    }
+
+    /// The indices with any starting weight, from the first to past the last.
+    pub fn span(&self) -> Range<u32> {
+        match (self.starts.first(), self.starts.last()) {
+            (Some(first), Some(last)) => first.index..last.index.saturating_add(1),
+            _ => 0..0,
+        }
+    }
+
+    pub fn observe(&mut self, evidence: Evidence) {
+        for set in self.sets.iter_mut() {
+            set.weight *= evidence.likelihood(set.ids());
+        }
+        self.evidence.push(evidence);
+    }
+
+    /// Takes back the last evidence, e.g. after a typo.
+    pub fn undo(&mut self) -> Option<Evidence> {
+        let evidence = self.evidence.pop();
+        self.reweigh();
+        evidence
+    }
+
+    pub fn evidence(&self) -> &[Evidence] {
+        &self.evidence
+    }
+
+    /// Factor all the evidence scales a set's weight by.
+    fn likelihood(&self, ids: [u16; 3]) -> f64 {
+        self.evidence.iter().map(|evidence| evidence.likelihood(ids)).product()
+    }
+
+    fn reweigh(&mut self) {
+        for pos in 0..self.sets.len() {
+            self.sets[pos].weight = self.sets[pos].prior * self.likelihood(self.sets[pos].ids());
+        }
+    }
+
+    fn total(&self) -> f64 {
+        self.sets.iter().map(|set| set.weight).sum()
+    }
+
+    /// Number of sets the evidence hasn't ruled out.
+    pub fn remaining(&self) -> usize {
+        self.sets.iter().filter(|set| set.weight > 0.0).count()
+    }
+
+    /// The sets that are still possible with their probabilities, likeliest
+    /// first. Empty if the evidence contradicts every set.
+    pub fn sets(&self) -> Vec<(PossibleSet, f64)> {
+        let total = self.total();
+        let mut sets: Vec<(PossibleSet, f64)> = self.sets.iter()
+            .filter(|set| set.weight > 0.0)
+            .map(|&set| (set, set.weight / total))
+            .collect();
+        sets.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.first_index.cmp(&b.0.first_index)));
+        sets
+    }
+
+    /// The chance of a piece at each location the remaining sets use,
+    /// likeliest first. Grabbed slots are left out.
+    pub fn locations(&self) -> Vec<Location> {
+        let total = self.total();
+        let mut locations: HashMap<u16, Location> = HashMap::new();
+        for set in self.sets.iter().filter(|set| set.weight > 0.0) {
+            for piece in set.pieces.iter().filter(|piece| piece.id != GRABBED_ID) {
+                locations.entry(piece.id)
+                    .or_insert(Location { piece: *piece, probability: 0.0 })
+                    .probability += set.weight / total;
+            }
+        }
+
+        let mut locations: Vec<Location> = locations.into_values().collect();
+        locations.sort_by(|a, b| b.probability.total_cmp(&a.probability).then(a.piece.id.cmp(&b.piece.id)));
+        locations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::emerald_manager::gen_set;
+    use crate::rng::{GcRng, Rng};
+    use crate::stage_spec::load_bundled;
+    use crate::Gc;
+
+    #[test]
+    fn test_posterior() {
+        let spec = load_bundled("GC/ph_spec_gc.txt");
+        let window = spec.pre_calls..spec.pre_calls + 5000;
+        let mut posterior = Posterior::new::<Gc>(&spec, [None, None, None], window.clone(), 1);
+
+        let sets = posterior.sets();
+        assert_eq!(sets.iter().map(|(set, _)| set.count).sum::<u64>(), 5000);
+        assert!((sets.iter().map(|&(_, p)| p).sum::<f64>() - 1.0).abs() < 1e-9);
+        let first = gen_set::<Gc>(&spec, Rng::at_index::<GcRng>(window.start), [None, None, None]).pieces;
+        assert!(sets.iter().any(|(set, _)| set.first_index == window.start && set.ids() == first.map(|piece| piece.id)));
+
+        // Every set has one piece per slot, so the chances add up to 3.
+        let locations = posterior.locations();
+        assert!((locations.iter().map(|location| location.probability).sum::<f64>() - 3.0).abs() < 1e-9);
+
+        // Checking the likeliest location and finding nothing rules it out.
+        let checked = locations[0].piece.id;
+        let before = posterior.remaining();
+        posterior.observe(Evidence::Empty { id: checked, miss_rate: 0.0 });
+        assert!(posterior.remaining() < before);
+        assert!(posterior.locations().iter().all(|location| location.piece.id != checked));
+
+        // With a chance of overlooking it, it only becomes less likely.
+        posterior.undo();
+        assert_eq!(posterior.remaining(), before);
+        posterior.observe(Evidence::Empty { id: checked, miss_rate: 0.25 });
+        let after = posterior.locations().iter().find(|location| location.piece.id == checked).unwrap().probability;
+        assert!(after > 0.0 && after < locations[0].probability);
+
+        // Finding a piece and seeing p3's hint narrow it down to matching sets.
+        posterior.observe(Evidence::Found(first[0].id));
+        posterior.observe(Evidence::Slot(2, PieceMatch::Id(first[2].id)));
+        assert!(posterior.sets().iter().all(|(set, _)| set.ids()[0] == first[0].id && set.ids()[2] == first[2].id));
+        assert_eq!(posterior.evidence().len(), 3);
+
+        posterior.observe(Evidence::Slot(2, PieceMatch::Not(Box::new(PieceMatch::Id(first[2].id)))));
+        assert_eq!(posterior.remaining(), 0);
+        assert!(posterior.sets().is_empty());
+        assert!(posterior.locations().is_empty());
+    }
+
+    #[test]
+    fn test_next_life() {
+        let spec = load_bundled("GC/ph_spec_gc.txt");
+        let window = spec.pre_calls..spec.pre_calls + 20_000;
+        let first = window.start + 12_345;
+        let gap = Gap { expected: 2400, tolerance: 10 };
+        let pieces = gen_set::<Gc>(&spec, Rng::at_index::<GcRng>(first), [None, None, None]).pieces;
+        let mut grabbed = pieces[0];
+        grabbed.id = GRABBED_ID;
+        let grabbed = [Some(grabbed), None, None];
+        let later = gen_set::<Gc>(&spec, Rng::at_index::<GcRng>(first + 2400), grabbed).pieces;
+
+        // p3's hint and p1 being got in the first life.
+        let mut posterior = Posterior::new::<Gc>(&spec, [None, None, None], window.clone(), 2);
+        posterior.observe(Evidence::Slot(2, PieceMatch::Id(pieces[2].id)));
+        posterior.observe(Evidence::Found(pieces[0].id));
+        let next = posterior.next_life::<Gc>(&spec, grabbed, gap, 2);
+        assert!(next.evidence().is_empty());
+        assert!(next.sets().iter().any(|(set, _)| set.ids() == later.map(|piece| piece.id)));
+        assert!((next.sets().iter().map(|&(_, p)| p).sum::<f64>() - 1.0).abs() < 1e-9);
+
+        // Starting over across the whole range the gap allows knows less.
+        let fresh = Posterior::new::<Gc>(&spec, grabbed, gap.after(window.clone()), 2);
+        let chance = |posterior: &Posterior| posterior.sets().iter()
+            .find(|(set, _)| set.ids() == later.map(|piece| piece.id))
+            .map_or(0.0, |&(_, p)| p);
+        assert!(next.remaining() < fresh.remaining());
+        assert!(chance(&next) > chance(&fresh));
+        let span = next.span();
+        assert!(span.start > window.start && span.end < window.end + gap.expected);
+
+        // Without evidence, every index the gap allows is still possible.
+        let flat = Posterior::new::<Gc>(&spec, [None, None, None], window.clone(), 2)
+            .next_life::<Gc>(&spec, grabbed, gap, 2);
+        assert_eq!(flat.span(), gap.after(window));
+        assert_eq!(flat.remaining(), fresh.remaining());
+    }
+}
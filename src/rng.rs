@@ -1,5 +1,8 @@
 use std::num::Wrapping;
 
+/// RNG state at boot. RNG indices count calls from this state.
+pub const SEED: u32 = 0xDEAD0CAB;
+
 pub trait RngConsts {
     const MULT_COEFFICIENT: u32;
     const ADD_COEFFICIENT: u32;
@@ -31,6 +34,15 @@ impl Rng {
         }
     }
 
+    /// RNG after `index` calls from the boot seed.
+    pub fn at_index<R>(index: u32) -> Rng
+        where R: RngConsts,
+    {
+        let mut r = Rng::new(SEED);
+        r.advance::<R>(index);
+        r
+    }
+
     pub fn gen_val<R>(&mut self) -> u32
         where R: RngConsts,
     {
@@ -42,7 +54,108 @@ impl Rng {
         (self.state.0 >> 0x10) & 0x7FFF
     }
 
+    /// Undoes one call to `gen_val`.
+    pub fn step_back<R>(&mut self)
+        where R: RngConsts,
+    {
+        let mult_inverse = mod_inverse(R::MULT_COEFFICIENT);
+        self.state = (self.state - Wrapping(R::ADD_COEFFICIENT)) * Wrapping(mult_inverse);
+    }
+
+    /// Same as calling `gen_val` `calls` times, in O(log calls).
+    pub fn advance<R>(&mut self, mut calls: u32)
+        where R: RngConsts,
+    {
+        let mut mult = Wrapping(R::MULT_COEFFICIENT);
+        let mut add = Wrapping(R::ADD_COEFFICIENT);
+
+        while calls != 0 {
+            if calls & 1 != 0 {
+                self.state = self.state * mult + add;
+            }
+            // Square the step: x -> m(mx + a) + a
+            add *= mult + Wrapping(1);
+            mult *= mult;
+            calls >>= 1;
+        }
+    }
+
+    /// Number of `gen_val` calls needed to get from this state to `target`.
+    /// Both LCGs have full period, so every state is reachable.
+    pub fn distance_to<R>(&self, target: u32) -> u32
+        where R: RngConsts,
+    {
+        let mut state = self.state;
+        let mut mult = Wrapping(R::MULT_COEFFICIENT);
+        let mut add = Wrapping(R::ADD_COEFFICIENT);
+        let mut calls = 0;
+
+        // The low n bits of the state cycle with period 2^n, so fix one bit at a time.
+        for bit in 0..32 {
+            let mask = 1u32 << bit;
+            if (state.0 ^ target) & mask != 0 {
+                state = state * mult + add;
+                calls |= mask;
+            }
+            add *= mult + Wrapping(1);
+            mult *= mult;
+        }
+
+        calls
+    }
+
+    /// RNG index (calls since the boot seed) of a raw state.
+    pub fn index_of<R>(state: u32) -> u32
+        where R: RngConsts,
+    {
+        Rng::new(SEED).distance_to::<R>(state)
+    }
+
     pub fn get_state(&self) -> u32 {
         self.state.0
     }
 }
+
+// Newton's iteration for the inverse of an odd number mod 2^32.
+fn mod_inverse(val: u32) -> u32 {
+    let val = Wrapping(val);
+    let mut inverse = val;
+    for _ in 0..5 {
+        inverse *= Wrapping(2) - val * inverse;
+    }
+    inverse.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_jumps<R>()
+        where R: RngConsts,
+    {
+        let mut r = Rng::new(SEED);
+        for _ in 0..10000 {
+            r.gen_val::<R>();
+        }
+
+        assert_eq!(Rng::at_index::<R>(10000).get_state(), r.get_state());
+        assert_eq!(Rng::index_of::<R>(r.get_state()), 10000);
+
+        for _ in 0..10000 {
+            r.step_back::<R>();
+        }
+        assert_eq!(r.get_state(), SEED);
+    }
+
+    #[test]
+    fn test_pc_jumps() {
+        check_jumps::<PcRng>();
+        assert_eq!(Rng::index_of::<PcRng>(Rng::at_index::<PcRng>(0xFFFF_FFFF).get_state()), 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn test_gc_jumps() {
+        check_jumps::<GcRng>();
+        assert_eq!(Rng::index_of::<GcRng>(Rng::at_index::<GcRng>(123_456_789).get_state()), 123_456_789);
+    }
+}
@@ -0,0 +1,223 @@
+//! Flags sets that depend on floating-point details.
+//!
+//! The p2 and p3 picks are positions in lists sorted by float keys. When the
+//! picked candidate's key is within rounding distance of another candidate's,
+//! a slightly different computation swaps the two and changes the set. This
+//! module measures that margin and also generates the set under every math
+//! model, so predictions resting on a near-tie can be marked as unreliable.
+
+use std::fmt;
+
+use crate::candidate_cache::CandidateCache;
+use crate::explain::{self, SlotPick};
+use crate::rng::Rng;
+use crate::stage_spec::{Emerald, StageSpec};
+use crate::vector::{GcFp, PcFp, PcX87Fp};
+use crate::x87::{DOUBLE, EXTENDED};
+use crate::{Platform, WithMath};
+
+/// Relative key margin under which a pick counts as unreliable by default,
+/// about a hundred single-precision ulps.
+pub const DEFAULT_TOLERANCE: f32 = 1e-5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MathModel {
+    /// Gekko instructions, as on GC.
+    Gekko,
+    /// f32 arithmetic rounded after every operation.
+    F32,
+    /// x87 at 53 bits of precision, stored as floats.
+    X87Double,
+    /// x87 at 64 bits of precision, stored as floats.
+    X87Extended,
+}
+
+/// Models for GC: Gekko, and f32 for when the compiler doesn't fuse
+/// multiply-adds.
+pub const GC_MATH_MODELS: &[MathModel] = &[MathModel::Gekko, MathModel::F32];
+
+/// Models for PC: each x87 precision, with f32 standing in for 24 bits.
+pub const PC_MATH_MODELS: &[MathModel] = &[MathModel::F32, MathModel::X87Double, MathModel::X87Extended];
+
+impl MathModel {
+    pub fn name(self) -> &'static str {
+        match self {
+            MathModel::Gekko => "gekko",
+            MathModel::F32 => "f32",
+            MathModel::X87Double => "x87-53",
+            MathModel::X87Extended => "x87-64",
+        }
+    }
+}
+
+impl fmt::Display for MathModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How much the set at one RNG index depends on float precision.
+#[derive(Clone, Debug)]
+pub struct Sensitivity {
+    /// The set under the platform's own math.
+    pub pieces: [Emerald; 3],
+    /// Relative gap between the picked p2's key and the closest other
+    /// candidate's, or `None` when p2 was grabbed. Zero for an exact tie.
+    pub p2_margin: Option<f32>,
+    /// Like `p2_margin`, for p3.
+    pub p3_margin: Option<f32>,
+    /// Math models that generate a different set, with the IDs they give.
+    pub other_sets: Vec<(MathModel, [u16; 3])>,
+}
+
+impl Sensitivity {
+    /// Whether a key error of `tolerance` could change the set, or another
+    /// math model already does.
+    pub fn is_reliable(&self, tolerance: f32) -> bool {
+        let margin_ok = |margin: Option<f32>| margin.is_none_or(|margin| margin > tolerance);
+        margin_ok(self.p2_margin) && margin_ok(self.p3_margin) && self.other_sets.is_empty()
+    }
+}
+
+/// Analyzes sets for one stage, with the platform's math models' candidate
+/// orders precomputed.
+pub struct Analyzer<'a, P> {
+    spec: &'a StageSpec,
+    grabbed: [Option<Emerald>; 3],
+    models: Vec<(MathModel, ModelCache<P>)>,
+}
+
+enum ModelCache<P> {
+    Gekko(CandidateCache<WithMath<P, GcFp>>),
+    F32(CandidateCache<WithMath<P, PcFp>>),
+    X87Double(CandidateCache<WithMath<P, PcX87Fp<DOUBLE>>>),
+    X87Extended(CandidateCache<WithMath<P, PcX87Fp<EXTENDED>>>),
+}
+
+impl<'a, P> Analyzer<'a, P>
+    where P: Platform,
+{
+    /// Compares against `P::MATH_MODELS`. Grabbed slots are given like in
+    /// `CandidateCache::with_grabbed`.
+    pub fn new(spec: &'a StageSpec, grabbed: [Option<Emerald>; 3]) -> Analyzer<'a, P> {
+        let models = P::MATH_MODELS.iter()
+            .map(|&model| {
+                let cache = match model {
+                    MathModel::Gekko => ModelCache::Gekko(CandidateCache::with_grabbed(spec, grabbed)),
+                    MathModel::F32 => ModelCache::F32(CandidateCache::with_grabbed(spec, grabbed)),
+                    MathModel::X87Double => ModelCache::X87Double(CandidateCache::with_grabbed(spec, grabbed)),
+                    MathModel::X87Extended => ModelCache::X87Extended(CandidateCache::with_grabbed(spec, grabbed)),
+                };
+                (model, cache)
+            })
+            .collect();
+
+        Analyzer {
+            spec,
+            grabbed,
+            models,
+        }
+    }
+
+    /// Analyzes the set generated from `r`.
+    pub fn analyze(&self, r: Rng) -> Sensitivity {
+        let explanation = explain::explain::<P>(self.spec, r, self.grabbed);
+        let pieces = [explanation.p1.piece, explanation.p2.piece, explanation.p3.piece];
+        let ids = pieces.map(|piece| piece.id);
+
+        let other_sets = self.models.iter()
+            .map(|(model, cache)| {
+                let mut r = r;
+                let pieces = match cache {
+                    ModelCache::Gekko(cache) => cache.gen_pieces(&mut r),
+                    ModelCache::F32(cache) => cache.gen_pieces(&mut r),
+                    ModelCache::X87Double(cache) => cache.gen_pieces(&mut r),
+                    ModelCache::X87Extended(cache) => cache.gen_pieces(&mut r),
+                };
+                (*model, pieces.map(|piece| piece.id))
+            })
+            .filter(|&(_, model_ids)| model_ids != ids)
+            .collect();
+
+        Sensitivity {
+            pieces,
+            p2_margin: margin(&explanation.p2),
+            p3_margin: margin(&explanation.p3),
+            other_sets,
+        }
+    }
+}
+
+/// Smallest relative gap between the picked candidate's key and any other.
+fn margin(pick: &SlotPick) -> Option<f32> {
+    let index = pick.draw?.index;
+    let picked = pick.candidates.get(index)?.key?;
+    let margin = pick.candidates.iter()
+        .enumerate()
+        .filter(|&(idx, _)| idx != index)
+        .filter_map(|(_, candidate)| candidate.key)
+        .map(|key| relative_gap(picked, key))
+        .fold(f32::INFINITY, f32::min);
+    Some(margin)
+}
+
+fn relative_gap(a: f32, b: f32) -> f32 {
+    let scale = a.abs().max(b.abs());
+    if scale == 0.0 {
+        0.0
+    }
+    else {
+        (a - b).abs() / scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::emerald_manager::gen_set;
+    use crate::explain::{Candidate, Draw};
+    use crate::stage_spec::load_bundled;
+    use crate::Pc;
+
+    #[test]
+    fn test_margin() {
+        let candidate = |key| Candidate { piece: Emerald::default(), key: Some(key) };
+        let pick = |index, keys: &[f32]| SlotPick {
+            draw: Some(Draw { state: 0, rand_val: 0, index }),
+            candidates: keys.iter().map(|&key| candidate(key)).collect(),
+            piece: Emerald::default(),
+        };
+
+        assert_eq!(margin(&pick(1, &[100.0, 200.0, 250.0])), Some(0.2));
+        assert_eq!(margin(&pick(0, &[0.0, 0.0, 250.0])), Some(0.0));
+        assert_eq!(margin(&pick(2, &[100.0, 200.0, 200.0])), Some(0.0));
+        assert_eq!(margin(&pick(0, &[100.0])), Some(f32::INFINITY));
+    }
+
+    #[test]
+    fn test_analyze() {
+        let spec = load_bundled("PC/dc_spec_pc.txt");
+        let analyzer = Analyzer::<Pc>::new(&spec, [None, None, None]);
+        assert_eq!(analyzer.models.iter().map(|&(model, _)| model).collect::<Vec<_>>(), PC_MATH_MODELS);
+        let mut flagged = Vec::new();
+        for index in spec.pre_calls..spec.pre_calls + 2000 {
+            let r = Rng::at_index::<crate::rng::PcRng>(index);
+            let sensitivity = analyzer.analyze(r);
+            let set = gen_set::<Pc>(&spec, r, [None, None, None]);
+            assert_eq!(sensitivity.pieces.map(|piece| piece.id), set.pieces.map(|piece| piece.id));
+            assert!(sensitivity.other_sets.iter().all(|&(model, _)| model != MathModel::F32));
+            if !sensitivity.is_reliable(DEFAULT_TOLERANCE) {
+                flagged.push(index);
+            }
+            assert!(!sensitivity.is_reliable(f32::INFINITY));
+        }
+
+        // At 500, p2 (0203) is about 1.4e-6 from the next candidate.
+        assert_eq!(flagged, [500, 519, 520, 1024, 1390, 1402]);
+        let near_tie = analyzer.analyze(Rng::at_index::<crate::rng::PcRng>(500));
+        assert_eq!(near_tie.pieces.map(|piece| piece.id), [0x0110, 0x0203, 0x0701]);
+        assert!(near_tie.p2_margin.unwrap() < 2e-6);
+        assert!(near_tie.p3_margin.unwrap() > DEFAULT_TOLERANCE);
+    }
+}
@@ -0,0 +1,143 @@
+use std::io::{self, Cursor, Read};
+
+use byteorder::{ByteOrder, BE, LE};
+use prs_util::decoder::Decoder;
+use sa2_set::SetFile;
+
+const SET_ENTRY_SIZE: usize = 0x20;
+
+/// Reads a SET file, decompressing it first if it is PRS-compressed.
+///
+/// PRS has no magic number, so the data is taken as-is when its object count
+/// matches its length and decoded otherwise.
+pub fn read_set_data<R>(mut read: R) -> io::Result<Vec<u8>>
+    where R: Read,
+{
+    let mut data = Vec::new();
+    read.read_to_end(&mut data)?;
+
+    if is_raw_set(&data) {
+        return Ok(data);
+    }
+
+    let decoded = Decoder::new(Cursor::new(&data)).decode_to_vec()
+        .ok()
+        .filter(|decoded| is_raw_set(decoded));
+
+    decoded.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "data is neither a SET file nor a PRS-compressed SET file"))
+}
+
+/// Parses the object list of a SET file in either byte order, decompressing
+/// it first if needed. The order whose object count fits the length wins.
+pub fn read_set_file<R>(read: R) -> io::Result<SetFile>
+    where R: Read,
+{
+    let data = read_set_data(read)?;
+    let count = BE::read_u32(&data) as usize;
+    if (count + 1).saturating_mul(SET_ENTRY_SIZE) <= data.len() {
+        SetFile::from_read::<BE, _>(Cursor::new(data))
+    }
+    else {
+        SetFile::from_read::<LE, _>(Cursor::new(data))
+    }
+}
+
+/// Whether `data` looks like an uncompressed SET file in either byte order:
+/// a 0x20-byte header whose first word is the number of 0x20-byte objects.
+pub fn is_raw_set(data: &[u8]) -> bool {
+    if data.len() < SET_ENTRY_SIZE {
+        return false;
+    }
+
+    let fits = |count: u32| {
+        let expected = (count as usize + 1).saturating_mul(SET_ENTRY_SIZE);
+        // Allow padding up to the next entry boundary.
+        data.len() >= expected && data.len() - expected < SET_ENTRY_SIZE
+    };
+
+    fits(BE::read_u32(data)) || fits(LE::read_u32(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compresses `data` as PRS with literals and short copies of the byte
+    /// before, which is enough to shrink the zeros of a SET file.
+    fn prs_compress(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut ctrl_pos = 0;
+        let mut bit = 8;
+        let mut put_bit = |out: &mut Vec<u8>, set: bool| {
+            if bit == 8 {
+                ctrl_pos = out.len();
+                out.push(0);
+                bit = 0;
+            }
+            if set {
+                out[ctrl_pos] |= 1 << bit;
+            }
+            bit += 1;
+        };
+
+        let mut pos = 0;
+        while pos < data.len() {
+            let run = data[pos..].iter().take(5).take_while(|&&b| pos > 0 && b == data[pos - 1]).count();
+            if run >= 2 {
+                let size = run - 2;
+                put_bit(&mut out, false);
+                put_bit(&mut out, false);
+                put_bit(&mut out, size & 2 != 0);
+                put_bit(&mut out, size & 1 != 0);
+                out.push(0xFF);
+                pos += run;
+            }
+            else {
+                put_bit(&mut out, true);
+                out.push(data[pos]);
+                pos += 1;
+            }
+        }
+        put_bit(&mut out, false);
+        put_bit(&mut out, true);
+        out.extend_from_slice(&[0, 0]);
+        out
+    }
+
+    #[test]
+    fn test_read_compressed() {
+        let mut raw = vec![0u8; 0x40];
+        raw[3] = 1;
+        BE::write_u16(&mut raw[0x20..], 0x000F);
+        BE::write_u16(&mut raw[0x22..], 0x0103);
+        BE::write_f32(&mut raw[0x28..], 12.5);
+
+        let compressed = prs_compress(&raw);
+        assert!(compressed.len() < raw.len());
+        assert!(!is_raw_set(&compressed));
+        assert_eq!(read_set_data(compressed.as_slice()).unwrap(), raw);
+
+        let set = read_set_file(compressed.as_slice()).unwrap();
+        assert_eq!(set.0.len(), 1);
+        assert_eq!((set.0[0].object.0, set.0[0].rotation.x, set.0[0].position.x), (0x000F, 0x0103, 12.5));
+
+        // Raw files pass through, and data that is neither is refused.
+        assert_eq!(read_set_data(raw.as_slice()).unwrap(), raw);
+        assert!(read_set_data(&compressed[..compressed.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_is_raw_set() {
+        let mut data = vec![0u8; 0x60];
+        data[3] = 2;
+        assert!(is_raw_set(&data));
+
+        data[3] = 0;
+        data[0] = 2;
+        assert!(is_raw_set(&data));
+
+        data[0] = 5;
+        assert!(!is_raw_set(&data));
+        assert!(!is_raw_set(&[0x02, 0x00]));
+    }
+}
@@ -0,0 +1,303 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+
+use crate::candidate_cache::CandidateCache;
+use crate::parallel;
+use crate::rng::RngConsts;
+use crate::stage_spec::StageSpec;
+use crate::Platform;
+
+const MAGIC: &[u8; 8] = b"SA2SETIX";
+const VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 40;
+
+/// Piece IDs of a generated set, in slot order.
+pub type SetIds = [u16; 3];
+
+/// Precomputed sets for a range of RNG indices of one stage and platform.
+///
+/// The file holds a table of every set the stage can generate, followed by
+/// one table index per RNG index, 2 or 3 bytes each. All values are
+/// little-endian:
+///
+/// ```text
+/// 0x00  magic "SA2SETIX"
+/// 0x08  u32 version
+/// 0x0C  u32 RNG multiplier (tells PC and GC apart)
+/// 0x10  u64 fingerprint of the stage's candidate orderings
+/// 0x18  u32 first RNG index
+/// 0x1C  u8  bytes per entry, then 3 bytes padding
+/// 0x20  u64 number of entries
+/// 0x28  u32 set count, then 3 u16 piece IDs per set
+///       entries
+/// ```
+///
+/// Sets are generated without grabbed pieces, so the index can't answer
+/// queries for a life after one was grabbed.
+#[derive(Debug)]
+pub struct SetIndex<R> {
+    reader: R,
+    multiplier: u32,
+    fingerprint: u64,
+    begin: u32,
+    width: usize,
+    count: u64,
+    table: Vec<SetIds>,
+    entries_offset: u64,
+}
+
+impl SetIndex<BufReader<File>> {
+    pub fn open<Pa>(path: Pa) -> io::Result<SetIndex<BufReader<File>>>
+        where Pa: AsRef<Path>,
+    {
+        SetIndex::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R> SetIndex<R>
+    where R: Read + Seek,
+{
+    pub fn new(mut reader: R) -> io::Result<SetIndex<R>> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a set index file"));
+        }
+        let version = reader.read_u32::<LE>()?;
+        if version != VERSION {
+            return Err(invalid_data(format!("unsupported set index version {}", version)));
+        }
+
+        let multiplier = reader.read_u32::<LE>()?;
+        let fingerprint = reader.read_u64::<LE>()?;
+        let begin = reader.read_u32::<LE>()?;
+        let width = reader.read_u8()? as usize;
+        reader.seek(SeekFrom::Current(3))?;
+        let count = reader.read_u64::<LE>()?;
+        if width != 2 && width != 3 {
+            return Err(invalid_data(format!("bad set index entry size {}", width)));
+        }
+
+        let num_sets = reader.read_u32::<LE>()?;
+        let mut table = Vec::with_capacity(num_sets as usize);
+        for _ in 0..num_sets {
+            table.push([reader.read_u16::<LE>()?, reader.read_u16::<LE>()?, reader.read_u16::<LE>()?]);
+        }
+
+        Ok(SetIndex {
+            reader,
+            multiplier,
+            fingerprint,
+            begin,
+            width,
+            count,
+            table,
+            entries_offset: HEADER_SIZE + 4 + num_sets as u64 * 6,
+        })
+    }
+
+    /// First RNG index covered.
+    pub fn begin(&self) -> u64 {
+        self.begin as u64
+    }
+
+    /// One past the last RNG index covered.
+    pub fn end(&self) -> u64 {
+        self.begin as u64 + self.count
+    }
+
+    /// Every set the stage can generate.
+    pub fn sets(&self) -> &[SetIds] {
+        &self.table
+    }
+
+    /// Checks that the index was built for this stage spec and platform.
+    pub fn check<P>(&self, spec: &StageSpec) -> io::Result<()>
+        where P: Platform,
+    {
+        if self.multiplier != P::Consts::MULT_COEFFICIENT {
+            return Err(invalid_data("set index was built for a different platform"));
+        }
+        if self.fingerprint != fingerprint(&CandidateCache::<P>::new(spec)) {
+            return Err(invalid_data("set index was built for a different stage spec or math model"));
+        }
+        Ok(())
+    }
+
+    /// The set at RNG index `index`, or `None` if the index doesn't cover it.
+    pub fn set_at(&mut self, index: u64) -> io::Result<Option<SetIds>> {
+        if index < self.begin() || index >= self.end() {
+            return Ok(None);
+        }
+
+        let offset = self.entries_offset + (index - self.begin()) * self.width as u64;
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut entry = [0; 3];
+        self.reader.read_exact(&mut entry[..self.width])?;
+        Ok(Some(self.table[self.decode(&entry)?]))
+    }
+
+    /// Calls `emit` for every index in `begin..end` whose set satisfies
+    /// `pred`, in index order. The range is clamped to what the index covers.
+    pub fn find<F, G>(&mut self, begin: u64, end: u64, mut pred: F, mut emit: G) -> io::Result<()>
+        where F: FnMut(&SetIds) -> bool,
+              G: FnMut(u64, &SetIds),
+    {
+        let matching: Vec<bool> = self.table.iter().map(&mut pred).collect();
+        let begin = begin.max(self.begin());
+        let end = end.min(self.end());
+        if begin >= end {
+            return Ok(());
+        }
+
+        self.reader.seek(SeekFrom::Start(self.entries_offset + (begin - self.begin()) * self.width as u64))?;
+
+        let mut buf = vec![0; self.width * 0x10000];
+        let mut index = begin;
+        while index < end {
+            let entries = ((end - index) as usize).min(0x10000);
+            let chunk = &mut buf[..entries * self.width];
+            self.reader.read_exact(chunk)?;
+            for entry in chunk.chunks(self.width) {
+                let code = self.decode(entry)?;
+                if matching[code] {
+                    emit(index, &self.table[code]);
+                }
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn decode(&self, entry: &[u8]) -> io::Result<usize> {
+        let code = entry.iter().rev().fold(0, |code, &byte| (code << 8) | byte as usize);
+        if code < self.table.len() {
+            Ok(code)
+        }
+        else {
+            Err(invalid_data(format!("set index entry {} is out of range", code)))
+        }
+    }
+}
+
+/// Builds an index of `count` RNG indices starting at `begin`, evaluated on
+/// `jobs` threads.
+pub fn build<P, W>(spec: &StageSpec, begin: u32, count: u64, jobs: usize, writer: W) -> io::Result<()>
+    where P: Platform,
+          W: Write,
+{
+    let cache = CandidateCache::<P>::new(spec);
+    let mut table: Vec<SetIds> = cache.reachable_sets().iter()
+        .map(|set| [set[0].id, set[1].id, set[2].id])
+        .collect();
+    table.sort_unstable();
+    table.dedup();
+    let width = if table.len() <= 0x10000 { 2 } else { 3 };
+
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(MAGIC)?;
+    writer.write_u32::<LE>(VERSION)?;
+    writer.write_u32::<LE>(P::Consts::MULT_COEFFICIENT)?;
+    writer.write_u64::<LE>(fingerprint(&cache))?;
+    writer.write_u32::<LE>(begin)?;
+    writer.write_all(&[width as u8, 0, 0, 0])?;
+    let count = count.min(parallel::FULL_PERIOD - begin as u64);
+    writer.write_u64::<LE>(count)?;
+
+    writer.write_u32::<LE>(table.len() as u32)?;
+    for set in &table {
+        for &id in set {
+            writer.write_u16::<LE>(id)?;
+        }
+    }
+
+    let mut result = Ok(());
+    parallel::scan::<P::Consts, _, _, _>(begin as u64, begin as u64 + count, jobs, |mut r| {
+        let pieces = cache.gen_pieces(&mut r);
+        let code = table.binary_search(&[pieces[0].id, pieces[1].id, pieces[2].id])
+            .expect("Generated set is missing from the reachable sets");
+        Some(code)
+    }, |_, code| {
+        if result.is_ok() {
+            result = writer.write_all(&code.to_le_bytes()[..width]);
+        }
+    });
+    result?;
+
+    writer.flush()
+}
+
+/// Where `search_set` looks for an index next to a stage-spec file.
+pub fn default_path<Pa>(spec_path: Pa) -> PathBuf
+    where Pa: AsRef<Path>,
+{
+    spec_path.as_ref().with_extension("idx")
+}
+
+/// FNV-1a hash of the reachable sets in cache order. It covers piece IDs and
+/// the candidate orderings, which depend on both positions and float math.
+fn fingerprint<P>(cache: &CandidateCache<P>) -> u64
+    where P: Platform,
+{
+    let mut hash: u64 = 0xCBF29CE484222325;
+    for set in cache.reachable_sets() {
+        for piece in &set {
+            for byte in piece.id.to_le_bytes().iter() {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001B3);
+            }
+        }
+    }
+    hash
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+    where E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::rng::{PcRng, Rng};
+    use crate::stage_spec::load_bundled;
+    use crate::{Gc, Pc};
+
+    #[test]
+    fn test_index_matches_generation() {
+        let spec = load_bundled("PC/dc_spec_pc.txt");
+        let mut data = Vec::new();
+        build::<Pc, _>(&spec, 100, 5000, 3, &mut data).unwrap();
+
+        let mut index = SetIndex::new(Cursor::new(data)).unwrap();
+        assert_eq!((index.begin(), index.end()), (100, 5100));
+        index.check::<Pc>(&spec).unwrap();
+        assert!(index.check::<Gc>(&load_bundled("GC/dc_spec_gc.txt")).is_err());
+        assert!(index.check::<Pc>(&load_bundled("PC/ph_spec_pc.txt")).is_err());
+
+        let cache = CandidateCache::<Pc>::new(&spec);
+        let mut expected = Vec::new();
+        for idx in 100..5100 {
+            let pieces = cache.gen_pieces(&mut Rng::at_index::<PcRng>(idx));
+            let ids = [pieces[0].id, pieces[1].id, pieces[2].id];
+            assert_eq!(index.set_at(idx as u64).unwrap(), Some(ids));
+            if ids[1] == 0x0A02 {
+                expected.push(idx as u64);
+            }
+        }
+        assert_eq!(index.set_at(99).unwrap(), None);
+        assert_eq!(index.set_at(5100).unwrap(), None);
+
+        let mut found = Vec::new();
+        index.find(0, u64::MAX, |ids| ids[1] == 0x0A02, |idx, _| found.push(idx)).unwrap();
+        assert!(!found.is_empty());
+        assert_eq!(found, expected);
+    }
+}
@@ -0,0 +1,199 @@
+//! Exact arithmetic on finite floating-point values, for the FPU models.
+
+/// A finite, nonzero value `mantissa * 2^exponent`, exact until rounded.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Exact {
+    negative: bool,
+    mantissa: u128,
+    exponent: i32,
+}
+
+impl Exact {
+    /// `None` for zero, infinities and NaN.
+    pub(crate) fn new(val: f64) -> Option<Exact> {
+        if !val.is_finite() || val == 0.0 {
+            return None;
+        }
+
+        let integral = val.to_bits();
+        let biased = ((integral >> 52) & 0x7FF) as i32;
+        let fraction = integral & ((1 << 52) - 1);
+        let (mantissa, exponent) = if biased == 0 {
+            (fraction, -1074)
+        }
+        else {
+            (fraction | (1 << 52), biased - 1075)
+        };
+
+        Some(Exact {
+            negative: integral >> 63 != 0,
+            mantissa: mantissa as u128,
+            exponent,
+        })
+    }
+
+    /// Bit position just above the mantissa, so the value lies in
+    /// [2^(top - 1), 2^top) times 2^exponent.
+    fn top(&self) -> i32 {
+        128 - self.mantissa.leading_zeros() as i32
+    }
+
+    /// The exact product. Both mantissas must fit in 64 bits, as they do for
+    /// doubles and for anything passed through `round`.
+    pub(crate) fn mul(self, other: Exact) -> Exact {
+        Exact {
+            negative: self.negative != other.negative,
+            mantissa: self.mantissa * other.mantissa,
+            exponent: self.exponent + other.exponent,
+        }
+    }
+
+    /// Sum, or `None` when it is exactly zero. The larger operand is placed
+    /// at the top of 126 bits and bits of the smaller one that fall off the
+    /// bottom are kept as a sticky bit, which leaves plenty of guard bits for
+    /// single-precision rounding.
+    pub(crate) fn add(self, other: Exact) -> Option<Exact> {
+        let (big, small) = if self.top() + self.exponent >= other.top() + other.exponent {
+            (self, other)
+        }
+        else {
+            (other, self)
+        };
+
+        let exponent = big.top() + big.exponent - 126;
+        let big_mantissa = big.mantissa << (big.exponent - exponent);
+        let shift = small.exponent - exponent;
+        let small_mantissa = if shift >= 0 {
+            small.mantissa << shift
+        }
+        else if -shift < 128 {
+            let lost = small.mantissa & ((1 << -shift) - 1);
+            (small.mantissa >> -shift) | (lost != 0) as u128
+        }
+        else {
+            1
+        };
+
+        let (negative, mantissa) = if big.negative == small.negative {
+            (big.negative, big_mantissa + small_mantissa)
+        }
+        else if big_mantissa >= small_mantissa {
+            (big.negative, big_mantissa - small_mantissa)
+        }
+        else {
+            (small.negative, small_mantissa - big_mantissa)
+        };
+
+        if mantissa == 0 {
+            None
+        }
+        else {
+            Some(Exact {
+                negative,
+                mantissa,
+                exponent,
+            })
+        }
+    }
+
+    pub(crate) fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub(crate) fn neg(self) -> Exact {
+        Exact {
+            negative: !self.negative,
+            ..self
+        }
+    }
+
+    /// Rounds to `bits` significant bits, to nearest even. The exponent range
+    /// is unlimited.
+    pub(crate) fn round(self, bits: i32) -> Exact {
+        let shift = self.top() - bits;
+        if shift <= 0 {
+            return self;
+        }
+
+        let kept = self.mantissa >> shift;
+        let rest = self.mantissa & ((1 << shift) - 1);
+        let half = 1u128 << (shift - 1);
+        let rounded = if rest > half || (rest == half && kept & 1 != 0) { kept + 1 } else { kept };
+        // Rounding up to the next power of two carries out of `bits`.
+        let carry = (rounded >> bits) as i32;
+        Exact {
+            negative: self.negative,
+            mantissa: rounded >> carry,
+            exponent: self.exponent + shift + carry,
+        }
+    }
+
+    /// Square root rounded to `bits` significant bits, for positive values.
+    pub(crate) fn sqrt(self, bits: i32) -> Exact {
+        debug_assert!(!self.negative);
+
+        // Root the mantissa shifted left by an even amount so that the
+        // exponent halves evenly and the root gets two bits beyond `bits`.
+        let mut shift = (2 * (bits + 2) - self.top()).max(0);
+        if (self.exponent - shift) % 2 != 0 {
+            shift += 1;
+        }
+        let width = self.top() + shift;
+        let bit = |i: i32| if i >= shift { (self.mantissa >> (i - shift)) as u32 & 1 } else { 0 };
+
+        // Digit-by-digit square root, two bits of the radicand at a time.
+        let mut root = 0u128;
+        let mut remainder = 0u128;
+        let mut i = width + width % 2;
+        while i > 0 {
+            i -= 2;
+            remainder = (remainder << 2) | (bit(i + 1) << 1 | bit(i)) as u128;
+            let trial = (root << 2) | 1;
+            root <<= 1;
+            if remainder >= trial {
+                remainder -= trial;
+                root |= 1;
+            }
+        }
+
+        // A root can't land exactly halfway between two results, so a sticky
+        // bit below the root is enough to round correctly.
+        Exact {
+            negative: false,
+            mantissa: root << 1 | (remainder != 0) as u128,
+            exponent: (self.exponent - shift) / 2 - 1,
+        }.round(bits)
+    }
+
+    /// Rounds to the nearest single, with denormals and overflow to infinity.
+    pub(crate) fn round_single(self) -> f64 {
+        let sign = if self.negative { -1.0 } else { 1.0 };
+        // The value is in [2^(magnitude - 1), 2^magnitude).
+        let magnitude = self.top() + self.exponent;
+        if magnitude > 128 {
+            return sign * f64::INFINITY;
+        }
+
+        // Keep 24 bits, or fewer for denormals, whose last bit is 2^-149.
+        let lowest = (magnitude - 24).max(-149);
+        let shift = lowest - self.exponent;
+        if shift <= 0 {
+            return sign * self.mantissa as f64 * 2f64.powi(self.exponent);
+        }
+        if shift > 128 {
+            return sign * 0.0;
+        }
+
+        let (kept, rest) = if shift == 128 {
+            (0, self.mantissa)
+        }
+        else {
+            (self.mantissa >> shift, self.mantissa & ((1 << shift) - 1))
+        };
+        let half = 1u128 << (shift - 1);
+        let rounded = if rest > half || (rest == half && kept & 1 != 0) { kept + 1 } else { kept };
+
+        // A rounded mantissa of 2^24 at the largest exponent overflows here.
+        (sign * rounded as f64 * 2f64.powi(lowest)) as f32 as f64
+    }
+}
@@ -0,0 +1,308 @@
+//! The sort routines emerald candidates are ordered with.
+//!
+//! Candidates with equal keys end up in an order that depends on the sort.
+//! `StableSort`, which keeps them in list order, is what every platform uses.
+//!
+//! The games sort with their C library's `qsort`, which is not stable.
+//! `MsvcQsort` and `MslQsort` reproduce MSVC's quicksort and CodeWarrior
+//! MSL's heapsort from the libraries' published sources, on the assumption
+//! that the PC port and the GC version call them. That hasn't been confirmed
+//! in either executable, and no tie has been observed in the game to tell the
+//! routines apart, so they are only used when asked for with `WithSort`
+//! (`--sort` in sa2pg).
+
+use std::cmp::Ordering;
+
+pub trait PlatformSort {
+    fn sort_by<T, F>(v: &mut [T], compare: F)
+        where F: FnMut(&T, &T) -> Ordering;
+}
+
+/// The candidate comparator: less and greater as usual, equal otherwise.
+/// Anything compared with NaN is therefore equal, like in the game.
+pub fn compare_keys(a: f32, b: f32) -> Ordering {
+    if a < b {
+        Ordering::Less
+    }
+    else if a > b {
+        Ordering::Greater
+    }
+    else {
+        Ordering::Equal
+    }
+}
+
+/// Compares two elements by signed index.
+fn cmp_at<T, F>(v: &[T], compare: &mut F, a: isize, b: isize) -> Ordering
+    where F: FnMut(&T, &T) -> Ordering,
+{
+    compare(&v[a as usize], &v[b as usize])
+}
+
+/// A stable binary insertion sort: equal candidates keep their list order.
+/// The standard library's sort may panic when NaN keys make the order
+/// inconsistent; this one never does and always gives the same result.
+pub struct StableSort;
+
+impl PlatformSort for StableSort {
+    fn sort_by<T, F>(v: &mut [T], mut compare: F)
+        where F: FnMut(&T, &T) -> Ordering,
+    {
+        for i in 1..v.len() {
+            // Insert after the last sorted element not greater than v[i].
+            let (mut lo, mut hi) = (0, i);
+            while lo < hi {
+                let mid = (lo + hi) / 2;
+                if compare(&v[mid], &v[i]) == Ordering::Greater {
+                    hi = mid;
+                }
+                else {
+                    lo = mid + 1;
+                }
+            }
+            v[lo..=i].rotate_right(1);
+        }
+    }
+}
+
+/// MSVC's `qsort`: median-of-three quicksort on an explicit stack, finishing
+/// partitions of up to 8 elements with a selection sort.
+pub struct MsvcQsort;
+
+const MSVC_CUTOFF: isize = 8;
+
+impl MsvcQsort {
+    /// Repeatedly swaps the largest remaining element to the end. The first
+    /// of several equal maxima is the one moved.
+    fn shortsort<T, F>(v: &mut [T], lo: isize, mut hi: isize, compare: &mut F)
+        where F: FnMut(&T, &T) -> Ordering,
+    {
+        while hi > lo {
+            let mut max = lo;
+            for p in lo + 1..=hi {
+                if cmp_at(v, compare, p, max) == Ordering::Greater {
+                    max = p;
+                }
+            }
+            v.swap(max as usize, hi as usize);
+            hi -= 1;
+        }
+    }
+}
+
+impl PlatformSort for MsvcQsort {
+    fn sort_by<T, F>(v: &mut [T], mut compare: F)
+        where F: FnMut(&T, &T) -> Ordering,
+    {
+        if v.len() < 2 {
+            return;
+        }
+
+        // Indices are signed because the partition loops can step one past
+        // either end before checking their bounds.
+        let compare = &mut compare;
+        let mut stack = Vec::new();
+        let mut lo: isize = 0;
+        let mut hi = v.len() as isize - 1;
+
+        loop {
+            let size = hi - lo + 1;
+            if size <= MSVC_CUTOFF {
+                Self::shortsort(v, lo, hi, compare);
+            }
+            else {
+                // Sort the first, middle and last elements, and partition
+                // around the middle one.
+                let mut mid = lo + size / 2;
+                if cmp_at(v, compare, lo, mid) == Ordering::Greater {
+                    v.swap(lo as usize, mid as usize);
+                }
+                if cmp_at(v, compare, lo, hi) == Ordering::Greater {
+                    v.swap(lo as usize, hi as usize);
+                }
+                if cmp_at(v, compare, mid, hi) == Ordering::Greater {
+                    v.swap(mid as usize, hi as usize);
+                }
+
+                let mut loguy = lo;
+                let mut higuy = hi;
+                loop {
+                    if mid > loguy {
+                        loguy += 1;
+                        while loguy < mid && cmp_at(v, compare, loguy, mid) != Ordering::Greater {
+                            loguy += 1;
+                        }
+                    }
+                    if mid <= loguy {
+                        loguy += 1;
+                        while loguy <= hi && cmp_at(v, compare, loguy, mid) != Ordering::Greater {
+                            loguy += 1;
+                        }
+                    }
+
+                    higuy -= 1;
+                    while higuy > mid && cmp_at(v, compare, higuy, mid) == Ordering::Greater {
+                        higuy -= 1;
+                    }
+
+                    if higuy < loguy {
+                        break;
+                    }
+                    v.swap(loguy as usize, higuy as usize);
+                    // The partition element moves along with the swap.
+                    if mid == higuy {
+                        mid = loguy;
+                    }
+                }
+
+                // Skip elements equal to the partition element on the low side.
+                higuy += 1;
+                if mid < higuy {
+                    higuy -= 1;
+                    while higuy > mid && cmp_at(v, compare, higuy, mid) == Ordering::Equal {
+                        higuy -= 1;
+                    }
+                }
+                if mid >= higuy {
+                    higuy -= 1;
+                    while higuy > lo && cmp_at(v, compare, higuy, mid) == Ordering::Equal {
+                        higuy -= 1;
+                    }
+                }
+
+                // Continue with the smaller side and stack the larger one.
+                if higuy - lo >= hi - loguy {
+                    if lo < higuy {
+                        stack.push((lo, higuy));
+                    }
+                    if loguy < hi {
+                        lo = loguy;
+                        continue;
+                    }
+                }
+                else {
+                    if loguy < hi {
+                        stack.push((loguy, hi));
+                    }
+                    if lo < higuy {
+                        hi = higuy;
+                        continue;
+                    }
+                }
+            }
+
+            match stack.pop() {
+                Some((l, h)) => {
+                    lo = l;
+                    hi = h;
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+/// CodeWarrior MSL's `qsort`: a heapsort, with the heap indexed from 1.
+pub struct MslQsort;
+
+impl PlatformSort for MslQsort {
+    fn sort_by<T, F>(v: &mut [T], mut compare: F)
+        where F: FnMut(&T, &T) -> Ordering,
+    {
+        if v.len() < 2 {
+            return;
+        }
+
+        let compare = &mut compare;
+        let at = |i: usize| i as isize - 1;
+        let mut r = v.len();
+        let mut l = r / 2 + 1;
+
+        loop {
+            // Build the heap from the middle down, then move the root to the
+            // end of the shrinking heap.
+            if l > 1 {
+                l -= 1;
+            }
+            else {
+                v.swap(at(l) as usize, at(r) as usize);
+                r -= 1;
+                if r == 1 {
+                    return;
+                }
+            }
+
+            let mut j = l;
+            while j * 2 <= r {
+                let i = j;
+                j *= 2;
+                if j < r && cmp_at(v, compare, at(j), at(j + 1)) == Ordering::Less {
+                    j += 1;
+                }
+                if cmp_at(v, compare, at(i), at(j)) == Ordering::Less {
+                    v.swap(at(i) as usize, at(j) as usize);
+                }
+                else {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rng::{PcRng, Rng};
+
+    fn check_sorts<S>()
+        where S: PlatformSort,
+    {
+        let mut r = Rng::new(0xDEAD0CAB);
+        for len in 0..100 {
+            // Few distinct keys so there are plenty of ties, and some NaNs.
+            let keys: Vec<f32> = (0..len)
+                .map(|_| match r.gen_val::<PcRng>() % 9 {
+                    8 => f32::NAN,
+                    key => key as f32,
+                })
+                .collect();
+            let mut finite: Vec<(f32, usize)> = keys.iter().cloned().zip(0..).filter(|(key, _)| !key.is_nan()).collect();
+
+            let mut sorted: Vec<(f32, usize)> = finite.clone();
+            S::sort_by(&mut sorted, |a, b| compare_keys(a.0, b.0));
+            assert!(sorted.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+            let mut ids: Vec<usize> = sorted.iter().map(|&(_, id)| id).collect();
+            ids.sort();
+            finite.sort_by_key(|&(_, id)| id);
+            assert_eq!(ids, finite.iter().map(|&(_, id)| id).collect::<Vec<_>>());
+
+            // NaN compares equal to everything, so only determinism is promised.
+            let mut first: Vec<(f32, usize)> = keys.iter().cloned().zip(0..).collect();
+            let mut second = first.clone();
+            S::sort_by(&mut first, |a, b| compare_keys(a.0, b.0));
+            S::sort_by(&mut second, |a, b| compare_keys(a.0, b.0));
+            assert_eq!(first.iter().map(|&(_, id)| id).collect::<Vec<_>>(), second.iter().map(|&(_, id)| id).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_sorts() {
+        check_sorts::<StableSort>();
+        check_sorts::<MsvcQsort>();
+        check_sorts::<MslQsort>();
+
+        // Only the stable sort keeps equal elements in place.
+        let ties = [(0.0, 'a'), (0.0, 'b'), (0.0, 'c')];
+        let mut stable = ties;
+        StableSort::sort_by(&mut stable, |a, b| compare_keys(a.0, b.0));
+        assert_eq!(stable.iter().map(|&(_, id)| id).collect::<String>(), "abc");
+        let mut msvc = ties;
+        MsvcQsort::sort_by(&mut msvc, |a, b| compare_keys(a.0, b.0));
+        assert_eq!(msvc.iter().map(|&(_, id)| id).collect::<String>(), "bca");
+        let mut msl = ties;
+        MslQsort::sort_by(&mut msl, |a, b| compare_keys(a.0, b.0));
+        assert_eq!(msl.iter().map(|&(_, id)| id).collect::<String>(), "bca");
+    }
+}
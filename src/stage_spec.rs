@@ -1,5 +1,6 @@
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{Seek, SeekFrom};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
 use std::path::Path;
 
 use serde_derive::{Serialize, Deserialize};
@@ -8,7 +9,9 @@ use process_reader::ProcessHandle;
 use byteorder::{ReadBytesExt, BE};
 
 use crate::vector::Vector;
+use crate::game_files::{GameFiles, Stage};
 use crate::rng::Rng;
+use crate::set_data;
 use crate::Platform;
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -33,6 +36,10 @@ pub struct StageSpec {
     pub slot3_pieces: Vec<Emerald>,
     pub enemy_pieces: Vec<Emerald>,
     pub pre_calls: u32,
+    /// RNG calls to add to `pre_calls` when the stage is entered another way,
+    /// by entry path name (e.g. "story", "stage-select", "restart", "retry").
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub entry_offsets: BTreeMap<String, i32>,
 }
 
 impl StageSpec {
@@ -90,6 +97,7 @@ impl StageSpec {
             slot3_pieces: p3_list,
             enemy_pieces: en_list,
             pre_calls: calls,
+            entry_offsets: BTreeMap::new(),
         }
     }
 
@@ -155,36 +163,45 @@ impl StageSpec {
             slot3_pieces: p3_list,
             enemy_pieces: en_list,
             pre_calls: calls,
+            entry_offsets: BTreeMap::new(),
         }
     }
 
     pub fn from_set_bin<A>(filename: A) -> StageSpec
         where A: AsRef<Path>
         {
-            let mut file = File::open(filename).unwrap();
-            let n_objects = file.read_u32::<BE>().unwrap();
-            file.seek(SeekFrom::Start(0x20)).unwrap();
+            let file = File::open(filename).unwrap();
+            Self::from_set_read(file).unwrap()
+        }
+
+    /// Same as `from_set_bin`, for SET data from any reader (e.g. a disc image).
+    /// PRS-compressed data is decompressed first.
+    pub fn from_set_read<R>(read: R) -> io::Result<StageSpec>
+        where R: Read,
+        {
+            let mut file = Cursor::new(set_data::read_set_data(read)?);
+            let n_objects = file.read_u32::<BE>()?;
+            file.seek(SeekFrom::Start(0x20))?;
             let mut p1_list: Vec<Emerald> = Vec::new();
             let mut p2_list: Vec<Emerald> = Vec::new();
             let mut p3_list: Vec<Emerald> = Vec::new();
             let mut pe_list: Vec<Emerald> = Vec::new();
             for i in 0..n_objects {
                 let object_pos: u32 = (i+1)*0x20;
-                println!("Object start: {object_pos:X}");
-                file.seek(SeekFrom::Start(object_pos.into())).unwrap();
-                let object_id = file.read_u16::<BE>().unwrap();
+                file.seek(SeekFrom::Start(object_pos.into()))?;
+                let object_id = file.read_u16::<BE>()?;
                 if object_id != 0xF {
                     continue;
                 }
                 
                 
-                let major_id = file.read_u8().unwrap();
-                let minor_id = file.read_u8().unwrap();
+                let major_id = file.read_u8()?;
+                let minor_id = file.read_u8()?;
                 let piece_id: u16 = (u16::from(major_id) << 8) + u16::from(minor_id);
-                file.seek(SeekFrom::Current(4)).unwrap();
-                let xpos = file.read_f32::<BE>().unwrap();
-                let ypos = file.read_f32::<BE>().unwrap();
-                let zpos = file.read_f32::<BE>().unwrap();
+                file.seek(SeekFrom::Current(4))?;
+                let xpos = file.read_f32::<BE>()?;
+                let ypos = file.read_f32::<BE>()?;
+                let zpos = file.read_f32::<BE>()?;
                 
                 
                 let em_obj = Emerald {
@@ -200,22 +217,31 @@ impl StageSpec {
                     4 | 7 | 8 => p3_list.push(em_obj),
                     0 | 2 | 5 => p2_list.push(em_obj),
                     1 | 3 => p1_list.push(em_obj),
-                    _ => {
-                        let seek_pos = file.stream_position().unwrap();
-                        println!("Position: {seek_pos:X}");
-                        println!("{major_id:X}");
-                    }
+                    _ => {}
                 }
 
             }
-            StageSpec {
+            Ok(StageSpec {
                 slot1_pieces: p1_list,
                 slot2_pieces: p2_list,
                 slot3_pieces: p3_list,
                 enemy_pieces: pe_list,
-                pre_calls: 136
-            }
+                pre_calls: 136,
+                entry_offsets: BTreeMap::new(),
+            })
         }
+    pub fn from_game_files(game: &GameFiles, stage: Stage) -> io::Result<StageSpec> {
+        let data = game.read_set(stage)?;
+        Self::from_set_read(Cursor::new(data))
+    }
+
+    /// RNG calls before generation when the stage is entered by `entry`, or
+    /// `None` if the spec has no offset for that entry path.
+    pub fn entry_pre_calls(&self, entry: &str) -> Option<u32> {
+        self.entry_offsets.get(entry)
+            .map(|&offset| self.pre_calls.wrapping_add_signed(offset))
+    }
+
     pub fn get_emerald_by_id(&self, id: u16) -> Option<Emerald> {
         for piece in &self.slot1_pieces {
             if piece.id == id {
@@ -244,3 +270,35 @@ impl StageSpec {
         None
     }
 }
+
+/// Loads one of the bundled specs for tests, e.g. `"PC/dc_spec_pc.txt"`.
+#[cfg(test)]
+pub(crate) fn load_bundled(path: &str) -> StageSpec {
+    let file = File::open(format!("{}/spec_files/{}", env!("CARGO_MANIFEST_DIR"), path)).unwrap();
+    serde_json::from_reader(file).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_offsets() {
+        let path = format!("{}/spec_files/PC/dc_spec_pc.txt", env!("CARGO_MANIFEST_DIR"));
+        let text = std::fs::read_to_string(path).unwrap();
+        let mut spec: StageSpec = serde_json::from_str(&text).unwrap();
+        assert!(spec.entry_offsets.is_empty());
+        assert_eq!(serde_json::to_string_pretty(&spec).unwrap(), text);
+
+        spec.entry_offsets.insert("story".to_string(), 4);
+        spec.entry_offsets.insert("restart".to_string(), -6);
+        assert_eq!(spec.entry_pre_calls("story"), Some(140));
+        assert_eq!(spec.entry_pre_calls("restart"), Some(130));
+        assert_eq!(spec.entry_pre_calls("retry"), None);
+
+        let json = serde_json::to_string(&spec).unwrap();
+        assert!(json.ends_with(r#""pre_calls":136,"entry_offsets":{"restart":-6,"story":4}}"#));
+        let read: StageSpec = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.entry_offsets, spec.entry_offsets);
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::constraint::GRABBED_ID;
+use crate::hint_lookup::{Hint, HintLookup};
+use crate::stage_spec::Emerald;
+
+/// Output formats for tabular tool output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Tsv,
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+    Markdown,
+}
+
+impl FromStr for Format {
+    type Err = UnknownFormatError;
+
+    fn from_str(s: &str) -> Result<Format, UnknownFormatError> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "json" => Ok(Format::Json),
+            "ndjson" | "jsonl" => Ok(Format::Ndjson),
+            "md" | "markdown" => Ok(Format::Markdown),
+            _ => Err(UnknownFormatError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UnknownFormatError(String);
+
+impl fmt::Display for UnknownFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown output format '{}'", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFormatError {}
+
+/// A single table value. Numbers stay numbers in JSON.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    Int(i64),
+    Float(f32),
+    Text(String),
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cell::Int(val) => write!(f, "{}", val),
+            Cell::Float(val) => write!(f, "{}", val),
+            Cell::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl From<&Cell> for Value {
+    fn from(cell: &Cell) -> Value {
+        match cell {
+            Cell::Int(val) => Value::from(*val),
+            // Go through the shortest f32 representation, so 0.1 doesn't
+            // come out as 0.10000000149011612.
+            Cell::Float(val) => val.to_string().parse::<f64>().map_or(Value::Null, Value::from),
+            Cell::Text(text) => Value::from(text.as_str()),
+        }
+    }
+}
+
+/// Writes rows one at a time, so long tables never sit in memory.
+pub struct TableWriter<W>
+    where W: Write,
+{
+    writer: Sink<W>,
+    format: Format,
+    headers: Vec<String>,
+    rows: usize,
+}
+
+/// CSV and TSV go through the csv crate for quoting; the rest is written
+/// directly.
+enum Sink<W>
+    where W: Write,
+{
+    Delimited(Box<csv::Writer<W>>),
+    Plain(W),
+}
+
+impl<W> TableWriter<W>
+    where W: Write,
+{
+    pub fn new(mut writer: W, format: Format, headers: Vec<String>) -> io::Result<TableWriter<W>> {
+        let writer = match format {
+            Format::Csv | Format::Tsv => {
+                let mut csv = csv_writer(writer, format);
+                csv.write_record(headers.iter().map(|header| escape_field(header, format)))?;
+                Sink::Delimited(Box::new(csv))
+            }
+            Format::Json => {
+                write!(writer, "[")?;
+                Sink::Plain(writer)
+            }
+            Format::Ndjson => Sink::Plain(writer),
+            Format::Markdown => {
+                writeln!(writer, "| {} |", headers.iter().map(|h| escape_markdown(h)).collect::<Vec<_>>().join(" | "))?;
+                writeln!(writer, "|{}", "---|".repeat(headers.len()))?;
+                Sink::Plain(writer)
+            }
+        };
+
+        Ok(TableWriter {
+            writer,
+            format,
+            headers,
+            rows: 0,
+        })
+    }
+
+    pub fn write_row(&mut self, row: &[Cell]) -> io::Result<()> {
+        assert_eq!(row.len(), self.headers.len(), "row length doesn't match the headers");
+
+        let format = self.format;
+        match self.writer {
+            Sink::Delimited(ref mut csv) => {
+                csv.write_record(row.iter().map(|cell| escape_field(&cell.to_string(), format)))?;
+            }
+            Sink::Plain(ref mut writer) => match format {
+                Format::Json => {
+                    let prefix = if self.rows == 0 { "\n" } else { ",\n" };
+                    write!(writer, "{}  {}", prefix, json_object(&self.headers, row))?;
+                }
+                Format::Markdown => {
+                    let line = row.iter()
+                        .map(|cell| escape_markdown(&cell.to_string()))
+                        .collect::<Vec<_>>();
+                    writeln!(writer, "| {} |", line.join(" | "))?;
+                }
+                _ => writeln!(writer, "{}", json_object(&self.headers, row))?,
+            },
+        }
+
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Closes the table and flushes the writer.
+    pub fn finish(self) -> io::Result<()> {
+        match self.writer {
+            Sink::Delimited(mut csv) => csv.flush(),
+            Sink::Plain(mut writer) => {
+                if self.format == Format::Json {
+                    writeln!(writer, "{}]", if self.rows == 0 { "" } else { "\n" })?;
+                }
+                writer.flush()
+            }
+        }
+    }
+}
+
+/// Column layout for one generated set per row, shared by the tools.
+///
+/// Field names, in order:
+///
+/// - `rng_index`: RNG calls from the boot seed
+/// - `state`: RNG state at that index, 8 hex digits
+/// - `p1`, `p2`, `p3`: piece IDs, 4 hex digits (or numbers with `hex` off);
+///   a grabbed slot shows FE00
+/// - `p1_x`, `p1_y`, `p1_z`, ... `p3_z`: piece positions
+/// - `p1_h1`, `p1_h2`, `p1_h3`, ... `p3_h3`: hint texts, per requested tier,
+///   with "N/A" for grabbed slots
+///
+/// Tools put their own leading columns (e.g. `offset`) before these.
+#[derive(Clone, Debug)]
+pub struct SetColumns {
+    pub hex: bool,
+    pub rng_index: bool,
+    pub state: bool,
+    pub positions: bool,
+    pub hint_tiers: Vec<usize>,
+}
+
+impl SetColumns {
+    pub fn headers(&self) -> Vec<String> {
+        let mut headers = Vec::new();
+        if self.rng_index {
+            headers.push("rng_index".to_string());
+        }
+        if self.state {
+            headers.push("state".to_string());
+        }
+        for slot in 1..=3 {
+            headers.push(format!("p{}", slot));
+        }
+        if self.positions {
+            for slot in 1..=3 {
+                for axis in ["x", "y", "z"].iter() {
+                    headers.push(format!("p{}_{}", slot, axis));
+                }
+            }
+        }
+        for slot in 1..=3 {
+            for tier in &self.hint_tiers {
+                headers.push(format!("p{}_h{}", slot, tier));
+            }
+        }
+        headers
+    }
+
+    /// Appends the cells for a set. Hints are left out without a lookup.
+    pub fn push_cells(&self, row: &mut Vec<Cell>, index: u64, state: u32, pieces: &[Emerald; 3], lookup: Option<&HintLookup>) {
+        if self.rng_index {
+            row.push(Cell::Int(index as i64));
+        }
+        if self.state {
+            row.push(Cell::Text(format!("{:08X}", state)));
+        }
+        for piece in pieces {
+            row.push(if self.hex { Cell::Text(format!("{:04X}", piece.id)) } else { Cell::Int(piece.id as i64) });
+        }
+        if self.positions {
+            for piece in pieces {
+                row.extend_from_slice(&[Cell::Float(piece.position.x), Cell::Float(piece.position.y), Cell::Float(piece.position.z)]);
+            }
+        }
+        if let Some(hints) = lookup {
+            for piece in pieces {
+                let hint = if piece.id == GRABBED_ID { None } else { Some(hints.lookup_piece(piece.id)) };
+                for &tier in &self.hint_tiers {
+                    row.push(Cell::Text(hint.map_or_else(|| "N/A".to_string(), |hint| hint_text(hint, tier))));
+                }
+            }
+        }
+    }
+}
+
+/// A hint tier's text on one line.
+pub fn hint_text(hint: &Hint, tier: usize) -> String {
+    let text = match tier {
+        1 => &hint.h1,
+        2 => &hint.h2,
+        _ => &hint.h3,
+    };
+    text.replace('\n', " ")
+}
+
+/// Written by hand to keep the columns in order.
+fn json_object(headers: &[String], row: &[Cell]) -> String {
+    let fields = headers.iter()
+        .zip(row)
+        .map(|(header, cell)| format!("{}: {}", Value::from(header.as_str()), Value::from(cell)))
+        .collect::<Vec<_>>();
+    format!("{{{}}}", fields.join(", "))
+}
+
+fn csv_writer<W: Write>(writer: W, format: Format) -> csv::Writer<W> {
+    let mut builder = csv::WriterBuilder::new();
+    builder.terminator(csv::Terminator::Any(b'\n'));
+    if format == Format::Tsv {
+        // TSV has no quoting; `escape_field` keeps separators out instead.
+        builder.delimiter(b'\t').quote_style(csv::QuoteStyle::Never);
+    }
+    builder.from_writer(writer)
+}
+
+fn escape_field(field: &str, format: Format) -> String {
+    match format {
+        Format::Tsv => field.replace(['\t', '\n', '\r'], " "),
+        _ => field.to_string(),
+    }
+}
+
+fn escape_markdown(field: &str) -> String {
+    field.replace('|', "\\|").replace(['\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(format: Format) -> String {
+        let headers = vec!["index".to_string(), "hint".to_string()];
+        let mut out = Vec::new();
+        let mut table = TableWriter::new(&mut out, format, headers).unwrap();
+        table.write_row(&[Cell::Int(3), Cell::Text("a, \"b\"|c".to_string())]).unwrap();
+        table.write_row(&[Cell::Int(4), Cell::Text("d\te".to_string())]).unwrap();
+        table.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(render(Format::Csv), "index,hint\n3,\"a, \"\"b\"\"|c\"\n4,d\te\n");
+        assert_eq!(render(Format::Tsv), "index\thint\n3\ta, \"b\"|c\n4\td e\n");
+        assert_eq!(render(Format::Markdown), "| index | hint |\n|---|---|\n| 3 | a, \"b\"\\|c |\n| 4 | d\te |\n");
+
+        let json: Value = serde_json::from_str(&render(Format::Json)).unwrap();
+        assert_eq!(json[0]["index"], 3);
+        assert_eq!(json[1]["hint"], "d\te");
+
+        let ndjson = render(Format::Ndjson);
+        let lines: Vec<Value> = ndjson.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], json[0]);
+    }
+}
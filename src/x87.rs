@@ -0,0 +1,142 @@
+//! Model of the x87 FPU the PC executable does its math on.
+//!
+//! Values loaded onto the register stack are rounded to the significand width
+//! set by the precision control field after every operation, and only rounded
+//! to single precision when stored to a float. `BITS` is that width: 24, 53
+//! or 64. Everything rounds to nearest even. Only finite values are modelled,
+//! as positions always are, and the exponent range is unlimited.
+//!
+//! Which width the game runs at hasn't been read from the executable. Windows
+//! starts threads at 53 bits, and Direct3D switches to 24 bits when a device
+//! is created without `D3DCREATE_FPU_PRESERVE`. Stages load well after the
+//! device is created, so `PC_PRECISION` assumes the flag isn't passed. At 24
+//! bits an expression gives the same result as f32 arithmetic; the other two
+//! can come out differently.
+
+use std::ops::{Add, Mul, Sub};
+
+use crate::soft_float::Exact;
+
+/// Precision control values, as significand widths.
+pub const SINGLE: i32 = 24;
+pub const DOUBLE: i32 = 53;
+pub const EXTENDED: i32 = 64;
+
+/// The precision the PC executable is assumed to compute at.
+pub const PC_PRECISION: i32 = SINGLE;
+
+/// A value on the register stack, rounded to `BITS` significant bits.
+#[derive(Clone, Copy, Debug)]
+pub struct Register<const BITS: i32>(Option<Exact>);
+
+impl<const BITS: i32> Register<BITS> {
+    /// `fld` of a float, which is exact.
+    pub fn load(val: f32) -> Register<BITS> {
+        debug_assert!(val.is_finite());
+        debug_assert!(matches!(BITS, SINGLE | DOUBLE | EXTENDED));
+        Register(Exact::new(val as f64))
+    }
+
+    /// `fstp` to a float.
+    pub fn store(self) -> f32 {
+        self.0.map_or(0.0, |val| val.round_single() as f32)
+    }
+
+    /// `fsqrt`.
+    pub fn sqrt(self) -> Register<BITS> {
+        debug_assert!(!self.0.is_some_and(|val| val.is_negative()));
+        Register(self.0.map(|val| val.sqrt(BITS)))
+    }
+}
+
+impl<const BITS: i32> Add<Register<BITS>> for Register<BITS> {
+    type Output = Register<BITS>;
+
+    fn add(self, other: Register<BITS>) -> Register<BITS> {
+        match (self.0, other.0) {
+            (Some(a), Some(b)) => Register(a.add(b).map(|sum| sum.round(BITS))),
+            (None, _) => other,
+            (_, None) => self,
+        }
+    }
+}
+
+impl<const BITS: i32> Sub<Register<BITS>> for Register<BITS> {
+    type Output = Register<BITS>;
+
+    fn sub(self, other: Register<BITS>) -> Register<BITS> {
+        match (self.0, other.0) {
+            (Some(a), Some(b)) => Register(a.add(b.neg()).map(|diff| diff.round(BITS))),
+            (None, _) => Register(other.0.map(Exact::neg)),
+            (_, None) => self,
+        }
+    }
+}
+
+impl<const BITS: i32> Mul<Register<BITS>> for Register<BITS> {
+    type Output = Register<BITS>;
+
+    fn mul(self, other: Register<BITS>) -> Register<BITS> {
+        match (self.0, other.0) {
+            (Some(a), Some(b)) => Register(Some(a.mul(b).round(BITS))),
+            _ => Register(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rng::{PcRng, Rng};
+
+    #[test]
+    fn test_precision() {
+        // 1 + 2^-24 + 2^-24 only survives with more than single precision.
+        let tiny = f32::from_bits(0x33800000);
+        assert_eq!(1.0 + tiny + tiny, 1.0);
+        assert_eq!((Register::<SINGLE>::load(1.0) + Register::load(tiny) + Register::load(tiny)).store(), 1.0);
+        assert_eq!((Register::<DOUBLE>::load(1.0) + Register::load(tiny) + Register::load(tiny)).store().to_bits(), 0x3F800001);
+        assert_eq!((Register::<EXTENDED>::load(1.0) + Register::load(tiny) + Register::load(tiny)).store().to_bits(), 0x3F800001);
+
+        // A significand of 64 bits keeps 2^-63 next to 1, and 2^-64 ties to
+        // even; 53 bits keep 2^-52 but not 2^-53.
+        let kept = |bits: u32| f32::from_bits(bits);
+        let one = Register::<EXTENDED>::load(1.0);
+        assert_eq!((one + Register::load(kept(0x20000000)) - one).store().to_bits(), 0x20000000);
+        assert_eq!((one + Register::load(kept(0x1F800000)) - one).store(), 0.0);
+        assert_eq!((one - one).store(), 0.0);
+        let one = Register::<DOUBLE>::load(1.0);
+        assert_eq!((one + Register::load(kept(0x25800000)) - one).store().to_bits(), 0x25800000);
+        assert_eq!((one + Register::load(kept(0x25000000)) - one).store(), 0.0);
+    }
+
+    fn check_single_results<const BITS: i32>() {
+        // Products of floats are exact at 53 or 64 bits and rounded once at
+        // 24, and a square root rounded to any of them then to single is
+        // still correctly rounded, so both agree with f32 arithmetic. Only a
+        // denormal product is rounded twice at 24 bits.
+        let mut r = Rng::new(0xDEAD0CAB);
+        let mut gen = || {
+            let bits = r.gen_val::<PcRng>() << 17 | r.gen_val::<PcRng>() << 2 | r.gen_val::<PcRng>() & 3;
+            f32::from_bits(bits & 0x3FFFFFFF | 0x10000000)
+        };
+        for _ in 0..100000 {
+            let (a, b) = (gen(), gen());
+            let (c, d) = (Register::<BITS>::load(a), Register::<BITS>::load(b));
+            if BITS > SINGLE || (a * b).is_normal() {
+                assert_eq!((c * d).store().to_bits(), (a * b).to_bits());
+            }
+            assert_eq!(c.sqrt().store().to_bits(), a.sqrt().to_bits());
+        }
+        assert_eq!(Register::<BITS>::load(4.0).sqrt().store(), 2.0);
+        assert_eq!(Register::<BITS>::load(0.0).sqrt().store(), 0.0);
+    }
+
+    #[test]
+    fn test_single_results() {
+        check_single_results::<SINGLE>();
+        check_single_results::<DOUBLE>();
+        check_single_results::<EXTENDED>();
+    }
+}